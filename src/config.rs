@@ -1,8 +1,12 @@
+use bstr::ByteSlice;
 use regex_automata::dfa::{dense, Automaton};
-use walkdir::WalkDir;
 
-use crate::{error, print_help, MatchOn};
-use std::{fs, path::PathBuf};
+use crate::{error, print_help};
+use grepox::{
+    walk::{walk, WalkOptions},
+    MatchOn,
+};
+use std::{fs, os::unix::prelude::OsStrExt, path::PathBuf};
 
 #[allow(clippy::upper_case_acronyms)]
 type DFA = dense::DFA<&'static [S]>;
@@ -25,12 +29,29 @@ const ALIGNED: &Aligned<[u8]> = &Aligned {
     bytes: *include_bytes!(concat!(env!("OUT_DIR"), "/plaintext_regex_le")),
 };
 
+#[derive(Clone, Copy)]
+enum Numeric {
+    Max,
+    After,
+    Before,
+    Context,
+    Threads,
+}
+
+#[derive(Clone, Copy)]
+enum Argument {
+    Pattern,
+    PatternFile,
+    Replace,
+}
+
 enum ConfigState {
     Flag,
     End,
     Invalid,
-    WantsMax,
-    Max(bool),
+    WantsNum(Numeric),
+    Num(Numeric, bool),
+    WantsArg(Argument),
     Space,
 }
 
@@ -38,20 +59,71 @@ pub struct ConfigParser {
     state: ConfigState,
     flags: Flags,
     max: u32,
+    after: u32,
+    before: u32,
+    threads: u32,
+    patterns: Option<Vec<String>>,
+    replace: Option<Vec<u8>>,
     match_on: MatchOn,
 }
 
 impl ConfigParser {
     #[inline]
     pub fn new() -> Self {
+        let smart_case = std::env::var_os("GREPOX_SMART_CASE")
+            .is_some_and(|value| !value.is_empty() && value != "0");
+
         Self {
             state: ConfigState::Space,
-            flags: Flags::default(),
+            flags: Flags {
+                smart_case,
+                ..Flags::default()
+            },
             max: 0,
+            after: 0,
+            before: 0,
+            threads: 0,
+            patterns: None,
+            replace: None,
             match_on: MatchOn::Anywhere,
         }
     }
 
+    #[inline]
+    fn num(&mut self, target: Numeric) -> &mut u32 {
+        match target {
+            Numeric::Max => &mut self.max,
+            Numeric::After => &mut self.after,
+            Numeric::Before | Numeric::Context => &mut self.before,
+            Numeric::Threads => &mut self.threads,
+        }
+    }
+
+    fn arg(&mut self, target: Argument, value: &[u8]) {
+        match target {
+            Argument::Pattern => self
+                .patterns
+                .get_or_insert_with(Vec::new)
+                .push(String::from_utf8_lossy(value).into_owned()),
+            Argument::Replace => self.replace = Some(value.to_vec()),
+            Argument::PatternFile => {
+                let path = std::ffi::OsStr::from_bytes(value);
+                let contents = fs::read(path).unwrap_or_else(|e| {
+                    error!(
+                        "Error reading pattern file {}: {}",
+                        path.to_string_lossy(),
+                        e
+                    )
+                });
+                self.patterns.get_or_insert_with(Vec::new).extend(
+                    contents
+                        .lines()
+                        .map(|line| String::from_utf8_lossy(line).into_owned()),
+                );
+            }
+        }
+    }
+
     #[inline]
     pub fn tick(&mut self, byte: u8) {
         match self.state {
@@ -60,39 +132,66 @@ impl ConfigParser {
             ConfigState::Flag => match byte {
                 b'-' => self.state = ConfigState::End,
                 b'i' => self.flags.case_insensitive = true,
+                b'S' => self.flags.smart_case = true,
                 b'n' => self.flags.show_lines = true,
+                b'b' => self.flags.byte_offset = true,
                 b'v' => self.flags.invert = true,
                 b'F' => self.flags.string_search = true,
                 b'c' => self.flags.color = true,
                 b'U' => self.flags.no_unicode = true,
                 b'q' => self.flags.quiet = true,
-                b'l' => self.flags.list = true,
+                b'l' => {
+                    self.flags.list = true;
+                    self.flags.list_without = false;
+                }
+                b'L' => {
+                    self.flags.list_without = true;
+                    self.flags.list = false;
+                }
+                b'o' => self.flags.only_matching = true,
+                b'a' => self.flags.text = true,
                 b'w' => self.match_on = MatchOn::Word,
                 b'x' => self.match_on = MatchOn::Line,
-                b'm' => self.state = ConfigState::WantsMax,
+                b'm' => self.state = ConfigState::WantsNum(Numeric::Max),
+                b'A' => self.state = ConfigState::WantsNum(Numeric::After),
+                b'B' => self.state = ConfigState::WantsNum(Numeric::Before),
+                b'C' => self.state = ConfigState::WantsNum(Numeric::Context),
+                b'j' => self.state = ConfigState::WantsNum(Numeric::Threads),
+                b'e' => self.state = ConfigState::WantsArg(Argument::Pattern),
+                b'f' => self.state = ConfigState::WantsArg(Argument::PatternFile),
+                b'r' => self.state = ConfigState::WantsArg(Argument::Replace),
                 b'h' => print_help!(),
                 b' ' => self.state = ConfigState::Space,
                 _ => self.state = ConfigState::Invalid,
             },
-            ConfigState::WantsMax => match byte {
+            ConfigState::WantsNum(target) => match byte {
                 b'=' | b' ' => {
-                    self.state = ConfigState::Max(false);
-                    self.max = 0;
+                    self.state = ConfigState::Num(target, false);
+                    *self.num(target) = 0;
+                }
+                b'0'..=b'9' => {
+                    self.state = ConfigState::Num(target, true);
+                    *self.num(target) = (byte - b'0') as u32;
                 }
                 _ => self.state = ConfigState::Invalid,
             },
-            ConfigState::Max(found) => match byte {
+            ConfigState::Num(target, found) => match byte {
                 b'0'..=b'9' => {
-                    self.state = ConfigState::Max(true);
-                    self.max = self.max * 10 + (byte - b'0') as u32
+                    self.state = ConfigState::Num(target, true);
+                    let num = self.num(target);
+                    *num = *num * 10 + (byte - b'0') as u32
                 }
                 b' ' => {
                     if found {
+                        if let Numeric::Context = target {
+                            self.after = self.before;
+                        }
                         self.state = ConfigState::Space
                     }
                 }
                 _ => self.state = ConfigState::Invalid,
             },
+            ConfigState::WantsArg(_) => (),
             ConfigState::Space => match byte {
                 b'-' => self.state = ConfigState::Flag,
                 b' ' => (),
@@ -101,12 +200,94 @@ impl ConfigParser {
         }
     }
 
+    #[inline]
+    fn long(&mut self, name: &[u8]) {
+        match name {
+            b"hidden" => self.flags.hidden = true,
+            b"no-ignore" => {
+                self.flags.no_ignore_vcs = true;
+                self.flags.no_ignore_dot = true;
+            }
+            b"no-ignore-vcs" => self.flags.no_ignore_vcs = true,
+            b"no-ignore-dot" => self.flags.no_ignore_dot = true,
+            b"json" => self.flags.json = true,
+            b"in-place" => self.flags.in_place = true,
+            b"backup" => self.flags.backup = true,
+            b"diff" => self.flags.diff = true,
+            b"count" => self.flags.count = true,
+            b"count-matches" => {
+                self.flags.count = true;
+                self.flags.count_matches = true;
+            }
+            b"count-total" => {
+                self.flags.count = true;
+                self.flags.count_total = true;
+            }
+            b"include-zero" => self.flags.include_zero = true,
+            b"column" => self.flags.column = true,
+            b"char-column" => {
+                self.flags.column = true;
+                self.flags.column_chars = true;
+            }
+            b"vimgrep" => self.flags.vimgrep = true,
+            b"multiline" => self.flags.multiline = true,
+            b"smart-case" => self.flags.smart_case = true,
+            b"no-smart-case" => self.flags.smart_case = false,
+            b"heading" => {
+                self.flags.heading = true;
+                self.flags.no_heading = false;
+            }
+            b"no-heading" => {
+                self.flags.no_heading = true;
+                self.flags.heading = false;
+            }
+            b"text" => self.flags.text = true,
+            b"mmap" => {
+                self.flags.mmap = true;
+                self.flags.no_mmap = false;
+            }
+            b"no-mmap" => {
+                self.flags.no_mmap = true;
+                self.flags.mmap = false;
+            }
+            b"help" => print_help!(),
+            _ => error!("Unknown option: --{}", String::from_utf8_lossy(name)),
+        }
+    }
+
     #[inline]
     pub fn run(&mut self, tape: &[u8]) -> bool {
-        for c in tape {
+        if let ConfigState::WantsArg(target) = self.state {
+            self.arg(target, tape);
+            self.state = ConfigState::Space;
+            return true;
+        }
+
+        if let ConfigState::Space = self.state {
+            match tape.strip_prefix(b"--") {
+                Some([]) => {
+                    self.state = ConfigState::End;
+                    return true;
+                }
+                Some(name) => {
+                    self.long(name);
+                    return true;
+                }
+                None => (),
+            }
+        }
+
+        for (i, c) in tape.iter().enumerate() {
             self.tick(*c);
-            if matches!(self.state, ConfigState::End) {
-                return false;
+            match self.state {
+                ConfigState::End => return false,
+                // The rest of the argument is the value, as in `-efoo`
+                ConfigState::WantsArg(target) if i + 1 < tape.len() => {
+                    self.arg(target, &tape[i + 1..]);
+                    self.state = ConfigState::Space;
+                    return true;
+                }
+                _ => (),
             }
         }
         self.tick(b' ');
@@ -118,6 +299,8 @@ impl ConfigParser {
 #[repr(align(8))]
 pub struct Flags {
     pub case_insensitive: bool,
+    pub smart_case: bool,
+    pub multiline: bool,
     pub show_lines: bool,
     pub invert: bool,
     pub string_search: bool,
@@ -126,12 +309,42 @@ pub struct Flags {
     pub quiet: bool,
     pub multiple_files: bool,
     pub list: bool,
+    pub list_without: bool,
+    pub hidden: bool,
+    pub no_ignore_vcs: bool,
+    pub no_ignore_dot: bool,
+    pub text: bool,
+    pub mmap: bool,
+    pub no_mmap: bool,
+    pub json: bool,
+    pub in_place: bool,
+    pub backup: bool,
+    pub diff: bool,
+    pub only_matching: bool,
+    pub count: bool,
+    pub count_matches: bool,
+    pub count_total: bool,
+    pub include_zero: bool,
+    pub byte_offset: bool,
+    pub column: bool,
+    pub column_chars: bool,
+    pub vimgrep: bool,
+    pub heading: bool,
+    pub no_heading: bool,
 }
 
 pub struct Config {
-    pub query: String,
+    /// A line matches if any of these match.
+    pub patterns: Vec<String>,
+    /// Template every match is replaced with in the output.
+    pub replace: Option<Vec<u8>>,
     pub filenames: Vec<PathBuf>,
     pub max: u32,
+    pub after_context: u32,
+    pub before_context: u32,
+    pub threads: u32,
+    /// Whether any directory was walked to collect `filenames`.
+    pub recursive: bool,
     pub flags: Flags,
     pub(crate) match_on: MatchOn,
 }
@@ -141,49 +354,175 @@ impl Config {
         let mut filenames = Vec::new();
         let mut parser = ConfigParser::new();
 
-        let mut args = std::env::args()
-            .skip(1)
-            .skip_while(|arg| parser.run(arg.as_bytes()));
+        // Paths need not be UTF-8, so arguments are taken as raw bytes
+        let mut args = std::env::args_os().skip(1);
+        let first = loop {
+            let Some(arg) = args.next() else {
+                break None;
+            };
+            if !parser.run(arg.as_bytes()) {
+                break Some(arg);
+            }
+        };
 
-        let query = args.next().unwrap_or_else(|| error!("No query specified"));
+        // Patterns given with -e or -f make every positional argument a file
+        if matches!(parser.state, ConfigState::WantsArg(_)) {
+            error!("Missing argument for -e, -f or -r");
+        }
+        let (patterns, first) = match parser.patterns.take() {
+            Some(patterns) => (patterns, first),
+            None => {
+                let query = first.unwrap_or_else(|| error!("No query specified"));
+                (
+                    vec![String::from_utf8_lossy(query.as_bytes()).into_owned()],
+                    None,
+                )
+            }
+        };
+
+        let walk_opts = WalkOptions {
+            hidden: parser.flags.hidden,
+            no_ignore_vcs: parser.flags.no_ignore_vcs,
+            no_ignore_dot: parser.flags.no_ignore_dot,
+        };
 
         let mut has_dir = false;
-        for arg in args {
+        for arg in first.into_iter().chain(args) {
             if let Ok(md) = fs::metadata(&arg) {
                 if md.is_file() {
                     filenames.push(arg.into());
                 } else if md.is_dir() {
                     has_dir = true;
-                    for entry in WalkDir::new(arg).into_iter().filter_map(Result::ok) {
-                        if entry.file_type().is_file() {
-                            filenames.push(entry.into_path());
-                        }
-                    }
+                    filenames.extend(walk(arg.as_ref(), walk_opts));
                 }
             }
         }
 
         parser.flags.multiple_files |= has_dir;
 
+        // An explicit -i wins over smart case
+        if parser.flags.smart_case && !parser.flags.case_insensitive {
+            let regex = !parser.flags.string_search;
+            parser.flags.case_insensitive =
+                !patterns.iter().any(|pattern| has_uppercase(pattern, regex));
+        }
+
         // Toggle string search if the query contains no special characters
         // This is done because string search is faster than regex search
-        if !parser.flags.string_search {
+        if !parser.flags.string_search && !parser.flags.multiline {
             let plain_text = DFA::from_bytes(&ALIGNED.bytes).unwrap().0;
-            if plain_text
-                .try_search_fwd(&query.as_bytes().into())
-                .map(|m| m.is_none())
-                .unwrap_or(false)
-            {
+            if patterns.iter().all(|pattern| {
+                plain_text
+                    .try_search_fwd(&pattern.as_bytes().into())
+                    .map(|m| m.is_none())
+                    .unwrap_or(false)
+            }) {
                 parser.flags.string_search = true;
             }
         }
 
         Self {
-            query,
+            patterns,
+            replace: parser.replace,
             filenames,
             max: parser.max,
+            after_context: parser.after,
+            before_context: parser.before,
+            threads: parser.threads,
+            recursive: has_dir,
             flags: parser.flags,
             match_on: parser.match_on,
         }
     }
 }
+
+/// Whether `pattern` contains an uppercase letter that it matches literally.
+///
+/// For regular expressions, escapes such as `\W`, `\p{Lu}` or `\xE9`, class names such as `[:upper:]`,
+/// group names and flags are skipped.
+fn has_uppercase(pattern: &str, regex: bool) -> bool {
+    if !regex {
+        return pattern.chars().any(char::is_uppercase);
+    }
+
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('p' | 'P' | 'x' | 'u' | 'U') if chars.peek() == Some(&'{') => {
+                    chars.find(|&c| c == '}');
+                }
+                Some('p' | 'P') => {
+                    chars.next();
+                }
+                // Fixed-width hex escapes like `\xE9`
+                Some(c @ ('x' | 'u' | 'U')) => {
+                    let width = match c {
+                        'x' => 2,
+                        'u' => 4,
+                        _ => 8,
+                    };
+                    for _ in 0..width {
+                        chars.next_if(char::is_ascii_hexdigit);
+                    }
+                }
+                _ => (),
+            },
+            '[' if chars.peek() == Some(&':') => {
+                while let Some(c) = chars.next() {
+                    if c == ':' && chars.peek() == Some(&']') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            '(' if chars.peek() == Some(&'?') => {
+                chars.find(|&c| matches!(c, ':' | ')' | '>'));
+            }
+            c if c.is_uppercase() => return true,
+            _ => (),
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::has_uppercase;
+
+    #[test]
+    fn has_uppercase_skips_regex_syntax() {
+        assert!(has_uppercase("Foo", true));
+        assert!(has_uppercase("foo\\WBar", true));
+        assert!(has_uppercase("ÄÖ", true));
+
+        for pattern in [
+            "foo\\W",
+            "\\D\\S\\B",
+            "\\p{Lu}",
+            "\\pL\\PN",
+            "[[:upper:]]",
+            "(?P<Name>foo)",
+            "(?<Name>foo)",
+            "(?-U:foo)",
+            "caf\\xE9",
+            "caf\\x{E9}",
+            "caf\\u00E9",
+            "caf\\U000000E9",
+        ] {
+            assert!(!has_uppercase(pattern, true), "{pattern}");
+        }
+    }
+
+    #[test]
+    fn has_uppercase_counts_letters_after_hex_escapes() {
+        assert!(has_uppercase("\\xE9A", true));
+        assert!(has_uppercase("\\x9G", true));
+    }
+
+    #[test]
+    fn has_uppercase_takes_literals_as_they_are() {
+        assert!(has_uppercase("\\W", false));
+        assert!(!has_uppercase("foo.*", false));
+    }
+}
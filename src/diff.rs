@@ -0,0 +1,218 @@
+use std::{
+    io::{self, Write},
+    os::unix::prelude::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use bstr::ByteSlice;
+
+use crate::sink::{Sink, SinkContext, SinkMatch};
+
+/// Prints the changes [`Searcher::replace`](crate::Searcher::replace) would make as a unified
+/// diff that `git apply` and `patch -p1` accept.
+///
+/// Every group of selected lines and their context becomes one hunk, so the amount of context is
+/// whatever the searcher reports. Selected lines the replacement leaves unchanged are printed as
+/// context and inputs without any change print nothing.
+pub struct DiffSink<W> {
+    writer: W,
+    path: PathBuf,
+    hunk: Vec<u8>,
+    old_start: u64,
+    old_lines: u64,
+    new_lines: u64,
+    changed: bool,
+    /// Lines added minus lines removed by the hunks of the current input so far.
+    offset: i64,
+    header_written: bool,
+}
+
+impl<W: Write> DiffSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            path: PathBuf::new(),
+            hunk: Vec::new(),
+            old_start: 0,
+            old_lines: 0,
+            new_lines: 0,
+            changed: false,
+            offset: 0,
+            header_written: false,
+        }
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Writes the output of another `DiffSink` that searched a single input.
+    #[inline]
+    pub fn write_output(&mut self, other: DiffSink<Vec<u8>>) -> io::Result<()> {
+        self.writer.write_all(&other.writer)
+    }
+
+    fn start_line(&mut self, line_number: u64) {
+        if self.hunk.is_empty() {
+            self.old_start = line_number;
+            self.old_lines = 0;
+            self.new_lines = 0;
+            self.changed = false;
+        }
+    }
+
+    fn push_line(&mut self, prefix: u8, line: &[u8]) {
+        self.hunk.push(prefix);
+        self.hunk.extend_from_slice(line);
+        if line.last() != Some(&b'\n') {
+            self.hunk
+                .extend_from_slice(b"\n\\ No newline at end of file\n");
+        }
+    }
+
+    fn push_context(&mut self, line_number: u64, line: &[u8]) {
+        self.start_line(line_number);
+        self.push_line(b' ', line);
+        self.old_lines += 1;
+        self.new_lines += 1;
+    }
+
+    fn flush_hunk(&mut self) -> io::Result<()> {
+        if !self.changed {
+            self.hunk.clear();
+            return Ok(());
+        }
+
+        let w = &mut self.writer;
+        if !self.header_written {
+            let path = diff_path(&self.path);
+            let path = path.as_os_str().as_bytes();
+            w.write_all(b"--- a/")?;
+            w.write_all(path)?;
+            w.write_all(b"\n+++ b/")?;
+            w.write_all(path)?;
+            w.write_all(b"\n")?;
+            self.header_written = true;
+        }
+
+        let new_start = self.old_start as i64 + self.offset;
+        writeln!(
+            w,
+            "@@ -{},{} +{},{} @@",
+            self.old_start, self.old_lines, new_start, self.new_lines
+        )?;
+        w.write_all(&self.hunk)?;
+
+        self.offset += self.new_lines as i64 - self.old_lines as i64;
+        self.hunk.clear();
+        self.changed = false;
+        Ok(())
+    }
+}
+
+/// The path written after `a/` and `b/` in the file headers.
+///
+/// Absolute paths are made relative to the working directory if they are below it, and lose
+/// their leading `/` otherwise, since `git apply` rejects absolute paths.
+fn diff_path(path: &Path) -> &Path {
+    if path.is_absolute() {
+        if let Some(relative) = std::env::current_dir()
+            .ok()
+            .and_then(|cwd| path.strip_prefix(cwd).ok())
+        {
+            return relative;
+        }
+        return path.strip_prefix("/").unwrap_or(path);
+    }
+    path.strip_prefix(".").unwrap_or(path)
+}
+
+impl<W: Write> Sink for DiffSink<W> {
+    fn begin(&mut self, path: &Path) -> io::Result<()> {
+        self.path = path.to_path_buf();
+        self.hunk.clear();
+        self.old_start = 0;
+        self.old_lines = 0;
+        self.new_lines = 0;
+        self.changed = false;
+        self.offset = 0;
+        self.header_written = false;
+        Ok(())
+    }
+
+    fn matched(&mut self, mat: &SinkMatch<'_>) -> io::Result<bool> {
+        let replaced = match mat.replacement {
+            Some(replaced) if replaced != mat.line => replaced,
+            _ => {
+                self.push_context(mat.line_number, mat.line);
+                return Ok(true);
+            }
+        };
+
+        self.start_line(mat.line_number);
+        self.push_line(b'-', mat.line);
+        self.old_lines += 1;
+        for line in replaced.lines_with_terminator() {
+            self.push_line(b'+', line);
+            self.new_lines += 1;
+        }
+        self.changed = true;
+        Ok(true)
+    }
+
+    fn context(&mut self, ctx: &SinkContext<'_>) -> io::Result<bool> {
+        self.push_context(ctx.line_number, ctx.line);
+        Ok(true)
+    }
+
+    fn binary_matched(&mut self, _path: &Path) -> io::Result<()> {
+        self.hunk.clear();
+        Ok(())
+    }
+
+    fn context_break(&mut self) -> io::Result<()> {
+        self.flush_hunk()
+    }
+
+    fn finish(&mut self, _path: &Path, _matches: u32) -> io::Result<()> {
+        self.flush_hunk()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use regex::bytes::Regex;
+
+    use super::DiffSink;
+    use crate::{RegexMatcher, Searcher};
+
+    #[test]
+    fn reused_sink_skips_inputs_without_changes() {
+        let matcher = RegexMatcher::new(Regex::new("^5$").unwrap());
+        let mut searcher = Searcher::new();
+        searcher.replace = Some(b"X".to_vec());
+        searcher.before_context = 3;
+        searcher.after_context = 3;
+
+        let mut sink = DiffSink::new(Vec::new());
+        let numbers = b"1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        searcher
+            .search_slice(&matcher, Path::new("n.txt"), numbers, &mut sink)
+            .unwrap();
+        searcher
+            .search_slice(&matcher, Path::new("other.txt"), b"a\nb\nc", &mut sink)
+            .unwrap();
+
+        let expected =
+            "--- a/n.txt\n+++ b/n.txt\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+X\n 6\n 7\n 8\n";
+        assert_eq!(String::from_utf8(sink.into_inner()).unwrap(), expected);
+    }
+}
@@ -0,0 +1,302 @@
+use std::{
+    fs, io,
+    os::unix::prelude::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use regex::bytes::{RegexSet, RegexSetBuilder};
+
+/// Outcome of matching a path against ignore rules.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Match {
+    /// No rule matched the path.
+    None,
+    /// The last matching rule excludes the path.
+    Ignore,
+    /// The last matching rule is negated with `!` and re-includes the path.
+    Whitelist,
+}
+
+struct Rule {
+    negated: bool,
+    dir_only: bool,
+}
+
+/// The rules of a single `.gitignore` or `.ignore` file.
+///
+/// Patterns follow gitignore semantics: `#` comments, `!` negation, trailing `/` for directories
+/// only, patterns containing a `/` are anchored to the directory of the file, `*`, `?`, `[...]`
+/// and `**` globs. The last matching rule wins.
+pub struct Gitignore {
+    root: PathBuf,
+    set: RegexSet,
+    rules: Vec<Rule>,
+}
+
+impl Gitignore {
+    /// Reads the ignore file at `path`, whose rules are relative to the directory it lives in.
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let contents = fs::read(path)?;
+        let root = path.parent().unwrap_or(Path::new(""));
+        Ok(Self::new(root, &contents))
+    }
+
+    /// Parses `contents` as ignore rules relative to `root`.
+    pub fn new(root: &Path, contents: &[u8]) -> Self {
+        let mut patterns = Vec::new();
+        let mut rules = Vec::new();
+
+        for line in contents.split(|&b| b == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let Some((pattern, rule)) = parse_line(line) else {
+                continue;
+            };
+            patterns.push(pattern);
+            rules.push(rule);
+        }
+
+        let build = |patterns: &[String]| RegexSetBuilder::new(patterns).unicode(false).build();
+        let set = build(&patterns).unwrap_or_else(|_| {
+            // Only a malformed character class can fail to compile, git never matches those
+            let (valid, valid_rules) = patterns
+                .iter()
+                .cloned()
+                .zip(rules.drain(..))
+                .filter(|(pattern, _)| build(std::slice::from_ref(pattern)).is_ok())
+                .unzip::<_, _, Vec<_>, Vec<_>>();
+            rules = valid_rules;
+            build(&valid).unwrap_or_else(|_| RegexSet::empty())
+        });
+
+        Self {
+            root: root.to_path_buf(),
+            set,
+            rules,
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Matches `path`, which must be inside the directory this file applies to.
+    pub fn matched(&self, path: &Path, is_dir: bool) -> Match {
+        if self.is_empty() {
+            return Match::None;
+        }
+        let Ok(rel) = path.strip_prefix(&self.root) else {
+            return Match::None;
+        };
+
+        let rel = rel.as_os_str().as_bytes();
+        let hit = self
+            .set
+            .matches(rel)
+            .into_iter()
+            .rev()
+            .find(|&i| is_dir || !self.rules[i].dir_only);
+
+        match hit {
+            None => Match::None,
+            Some(i) if self.rules[i].negated => Match::Whitelist,
+            Some(_) => Match::Ignore,
+        }
+    }
+}
+
+fn parse_line(mut line: &[u8]) -> Option<(String, Rule)> {
+    if line.first() == Some(&b'#') {
+        return None;
+    }
+
+    // Trailing spaces are dropped unless escaped with a backslash
+    while let Some(rest) = line.strip_suffix(b" ") {
+        if rest.last() == Some(&b'\\') {
+            break;
+        }
+        line = rest;
+    }
+
+    let negated = line.first() == Some(&b'!');
+    if negated {
+        line = &line[1..];
+    }
+
+    let dir_only = line.last() == Some(&b'/');
+    if dir_only {
+        line = &line[..line.len() - 1];
+    }
+
+    if line.is_empty() {
+        return None;
+    }
+
+    let anchored = line.contains(&b'/');
+    let line = line.strip_prefix(b"/").unwrap_or(line);
+
+    let mut re = String::from(if anchored { "^" } else { "^(?:.*/)?" });
+    glob_to_regex(line, &mut re);
+    re.push('$');
+
+    Some((re, Rule { negated, dir_only }))
+}
+
+fn glob_to_regex(glob: &[u8], re: &mut String) {
+    let mut i = 0;
+    while i < glob.len() {
+        match glob[i] {
+            b'*' if glob.get(i + 1) == Some(&b'*') => {
+                let at_start = i == 0 || glob[i - 1] == b'/';
+                let at_end = i + 2 == glob.len();
+                if at_start && glob.get(i + 2) == Some(&b'/') {
+                    // `**/` matches zero or more leading directories
+                    re.push_str("(?:.*/)?");
+                    i += 3;
+                } else if at_start && at_end {
+                    // trailing `/**` matches everything inside
+                    re.push_str(".*");
+                    i += 2;
+                } else {
+                    re.push_str("[^/]*");
+                    i += 2;
+                }
+                continue;
+            }
+            b'*' => re.push_str("[^/]*"),
+            b'?' => re.push_str("[^/]"),
+            b'[' => {
+                let Some(len) = glob[i + 1..].iter().skip(1).position(|&b| b == b']') else {
+                    re.push_str("\\[");
+                    i += 1;
+                    continue;
+                };
+                let class = &glob[i + 1..i + 2 + len];
+                re.push('[');
+                let class = match class.first() {
+                    Some(b'!' | b'^') => {
+                        re.push('^');
+                        &class[1..]
+                    }
+                    _ => class,
+                };
+                for &b in class {
+                    if matches!(b, b'[' | b'\\' | b'&' | b'~') {
+                        re.push('\\');
+                    }
+                    push_byte(re, b);
+                }
+                re.push(']');
+                i += len + 3;
+                continue;
+            }
+            b'\\' if i + 1 < glob.len() => {
+                i += 1;
+                push_literal(re, glob[i]);
+            }
+            b => push_literal(re, b),
+        }
+        i += 1;
+    }
+}
+
+#[inline]
+fn push_literal(re: &mut String, b: u8) {
+    if b.is_ascii() {
+        re.push_str(&regex::escape(char::from(b).encode_utf8(&mut [0; 4])));
+    } else {
+        push_byte(re, b);
+    }
+}
+
+#[inline]
+fn push_byte(re: &mut String, b: u8) {
+    if b.is_ascii() {
+        re.push(char::from(b));
+    } else {
+        re.push_str(&format!("\\x{b:02X}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{Gitignore, Match};
+
+    fn matched(rules: &str, path: &str, is_dir: bool) -> Match {
+        let ignore = Gitignore::new(Path::new("root"), rules.as_bytes());
+        ignore.matched(&Path::new("root").join(path), is_dir)
+    }
+
+    #[test]
+    fn leading_double_star_matches_any_directory() {
+        assert_eq!(matched("**/foo", "foo", false), Match::Ignore);
+        assert_eq!(matched("**/foo", "a/b/foo", false), Match::Ignore);
+        assert_eq!(matched("**/foo", "a/foobar", false), Match::None);
+        assert_eq!(matched("a/**/b", "a/b", false), Match::Ignore);
+        assert_eq!(matched("a/**/b", "a/x/y/b", false), Match::Ignore);
+    }
+
+    #[test]
+    fn trailing_double_star_matches_everything_inside() {
+        assert_eq!(matched("a/**", "a/x", false), Match::Ignore);
+        assert_eq!(matched("a/**", "a/x/y", false), Match::Ignore);
+        assert_eq!(matched("a/**", "a", true), Match::None);
+        assert_eq!(matched("a/**", "b/a/x", false), Match::None);
+    }
+
+    #[test]
+    fn slashes_anchor_patterns() {
+        assert_eq!(matched("/foo", "foo", false), Match::Ignore);
+        assert_eq!(matched("/foo", "a/foo", false), Match::None);
+        assert_eq!(matched("a/foo", "a/foo", false), Match::Ignore);
+        assert_eq!(matched("a/foo", "b/a/foo", false), Match::None);
+        assert_eq!(matched("foo", "b/a/foo", false), Match::Ignore);
+        assert_eq!(matched("*.txt", "a/b.txt", false), Match::Ignore);
+        assert_eq!(matched("a/*.txt", "a/b/c.txt", false), Match::None);
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let rules = "*.log\n!keep.log\n";
+        assert_eq!(matched(rules, "a.log", false), Match::Ignore);
+        assert_eq!(matched(rules, "x/keep.log", false), Match::Whitelist);
+        assert_eq!(
+            matched("!keep.log\n*.log\n", "keep.log", false),
+            Match::Ignore
+        );
+        assert_eq!(matched("\\!bang", "!bang", false), Match::Ignore);
+    }
+
+    #[test]
+    fn trailing_slash_matches_only_directories() {
+        assert_eq!(matched("build/", "build", true), Match::Ignore);
+        assert_eq!(matched("build/", "a/build", true), Match::Ignore);
+        assert_eq!(matched("build/", "build", false), Match::None);
+    }
+
+    #[test]
+    fn character_classes() {
+        assert_eq!(matched("[!a]bc", "xbc", false), Match::Ignore);
+        assert_eq!(matched("[!a]bc", "abc", false), Match::None);
+        assert_eq!(matched("[a-c]x", "bx", false), Match::Ignore);
+        assert_eq!(matched("[a-c]x", "dx", false), Match::None);
+        assert_eq!(matched("[]]x", "]x", false), Match::Ignore);
+    }
+
+    #[test]
+    fn trailing_spaces_are_dropped_unless_escaped() {
+        assert_eq!(matched("foo  ", "foo", false), Match::Ignore);
+        assert_eq!(matched("foo  ", "foo ", false), Match::None);
+        assert_eq!(matched("foo\\ ", "foo ", false), Match::Ignore);
+        assert_eq!(matched("foo\\ ", "foo", false), Match::None);
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let ignore = Gitignore::new(Path::new("root"), b"# foo\n\n   \n");
+        assert!(ignore.is_empty());
+        assert_eq!(matched("\\#foo", "#foo", false), Match::Ignore);
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    io::{self, Write},
+    ops::Range,
+    os::unix::prelude::OsStrExt,
+    path::Path,
+};
+
+use crate::sink::{Sink, SinkContext, SinkMatch};
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Aggregate statistics of a search.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct Stats {
+    /// Number of inputs searched.
+    pub searches: u64,
+    /// Number of inputs with at least one selected line.
+    pub searches_with_match: u64,
+    /// Number of selected lines.
+    pub matched_lines: u64,
+    /// Number of individual matches inside selected lines.
+    pub matches: u64,
+}
+
+impl Stats {
+    #[inline]
+    pub fn add(&mut self, other: &Stats) {
+        self.searches += other.searches;
+        self.searches_with_match += other.searches_with_match;
+        self.matched_lines += other.matched_lines;
+        self.matches += other.matches;
+    }
+}
+
+/// Prints search events as JSON Lines, one object per event.
+///
+/// Every object has a `type` (`begin`, `match`, `context`, `end` or `summary`) and a `data`
+/// field. Paths, lines and submatches are written as `{"text":...}` when they are valid UTF-8
+/// and as `{"bytes":...}` holding standard base64 otherwise, so no input is ever lost.
+pub struct JsonSink<W> {
+    writer: W,
+    stats: Stats,
+    file_matches: u64,
+    binary: bool,
+}
+
+impl<W: Write> JsonSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            stats: Stats::default(),
+            file_matches: 0,
+            binary: false,
+        }
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Statistics of everything searched through this sink so far.
+    #[inline]
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Writes the events of another `JsonSink` and adds its statistics to this one.
+    pub fn write_output(&mut self, other: JsonSink<Vec<u8>>) -> io::Result<()> {
+        self.stats.add(&other.stats);
+        self.writer.write_all(&other.writer)
+    }
+
+    /// Writes the `summary` event with the statistics of the whole search.
+    pub fn write_summary(&mut self) -> io::Result<()> {
+        let Stats {
+            searches,
+            searches_with_match,
+            matched_lines,
+            matches,
+        } = self.stats;
+        writeln!(
+            self.writer,
+            r#"{{"type":"summary","data":{{"stats":{{"searches":{searches},"searches_with_match":{searches_with_match},"matched_lines":{matched_lines},"matches":{matches}}}}}}}"#
+        )
+    }
+
+    fn write_line_event(
+        &mut self,
+        kind: &str,
+        path: &Path,
+        line_number: u64,
+        absolute_offset: u64,
+        line: &[u8],
+        ranges: &[Range<usize>],
+    ) -> io::Result<()> {
+        let w = &mut self.writer;
+        write!(w, r#"{{"type":"{kind}","data":{{"path":"#)?;
+        write_data(w, path.as_os_str().as_bytes())?;
+        w.write_all(br#","lines":"#)?;
+        write_data(w, line)?;
+        write!(
+            w,
+            r#","line_number":{line_number},"absolute_offset":{absolute_offset},"submatches":["#
+        )?;
+        for (i, range) in ranges.iter().enumerate() {
+            if i > 0 {
+                w.write_all(b",")?;
+            }
+            w.write_all(br#"{"match":"#)?;
+            write_data(w, &line[range.clone()])?;
+            write!(w, r#","start":{},"end":{}}}"#, range.start, range.end)?;
+        }
+        w.write_all(b"]}}\n")
+    }
+}
+
+impl<W: Write> Sink for JsonSink<W> {
+    #[inline]
+    fn wants_ranges(&self) -> bool {
+        true
+    }
+
+    fn begin(&mut self, path: &Path) -> io::Result<()> {
+        self.file_matches = 0;
+        self.binary = false;
+
+        let w = &mut self.writer;
+        w.write_all(br#"{"type":"begin","data":{"path":"#)?;
+        write_data(w, path.as_os_str().as_bytes())?;
+        w.write_all(b"}}\n")
+    }
+
+    fn matched(&mut self, mat: &SinkMatch<'_>) -> io::Result<bool> {
+        self.file_matches += mat.match_count as u64;
+        self.write_line_event(
+            "match",
+            mat.path,
+            mat.line_number,
+            mat.absolute_offset,
+            mat.line,
+            mat.ranges,
+        )?;
+        Ok(true)
+    }
+
+    fn context(&mut self, ctx: &SinkContext<'_>) -> io::Result<bool> {
+        self.write_line_event(
+            "context",
+            ctx.path,
+            ctx.line_number,
+            ctx.absolute_offset,
+            ctx.line,
+            &[],
+        )?;
+        Ok(true)
+    }
+
+    fn binary_matched(&mut self, _path: &Path) -> io::Result<()> {
+        self.binary = true;
+        Ok(())
+    }
+
+    fn finish(&mut self, path: &Path, matches: u32) -> io::Result<()> {
+        self.stats.searches += 1;
+        self.stats.searches_with_match += (matches > 0) as u64;
+        self.stats.matched_lines += matches as u64;
+        self.stats.matches += self.file_matches;
+
+        let w = &mut self.writer;
+        w.write_all(br#"{"type":"end","data":{"path":"#)?;
+        write_data(w, path.as_os_str().as_bytes())?;
+        writeln!(
+            w,
+            r#","binary":{},"stats":{{"matched_lines":{},"matches":{}}}}}}}"#,
+            self.binary, matches, self.file_matches
+        )
+    }
+}
+
+/// Writes `bytes` as `{"text":...}` if they are valid UTF-8, `{"bytes":...}` otherwise.
+fn write_data<W: Write>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => {
+            w.write_all(br#"{"text":""#)?;
+            write_escaped(w, text)?;
+            w.write_all(br#""}"#)
+        }
+        Err(_) => {
+            w.write_all(br#"{"bytes":""#)?;
+            write_base64(w, bytes)?;
+            w.write_all(br#""}"#)
+        }
+    }
+}
+
+fn write_escaped<W: Write>(w: &mut W, text: &str) -> io::Result<()> {
+    let bytes = text.as_bytes();
+    let mut last = 0;
+    for (i, &b) in bytes.iter().enumerate() {
+        let escape: &[u8] = match b {
+            b'"' => br#"\""#,
+            b'\\' => br"\\",
+            b'\n' => br"\n",
+            b'\r' => br"\r",
+            b'\t' => br"\t",
+            0..=0x1f | 0x7f => b"",
+            _ => continue,
+        };
+        w.write_all(&bytes[last..i])?;
+        if escape.is_empty() {
+            write!(w, "\\u{b:04x}")?;
+        } else {
+            w.write_all(escape)?;
+        }
+        last = i + 1;
+    }
+    w.write_all(&bytes[last..])
+}
+
+fn write_base64<W: Write>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+    let mut chunks = bytes.chunks_exact(3);
+    for chunk in &mut chunks {
+        let n = u32::from_be_bytes([0, chunk[0], chunk[1], chunk[2]]);
+        w.write_all(&[
+            BASE64[(n >> 18) as usize & 63],
+            BASE64[(n >> 12) as usize & 63],
+            BASE64[(n >> 6) as usize & 63],
+            BASE64[n as usize & 63],
+        ])?;
+    }
+
+    match *chunks.remainder() {
+        [a] => {
+            let n = u32::from(a) << 16;
+            w.write_all(&[
+                BASE64[(n >> 18) as usize & 63],
+                BASE64[(n >> 12) as usize & 63],
+                b'=',
+                b'=',
+            ])
+        }
+        [a, b] => {
+            let n = u32::from(a) << 16 | u32::from(b) << 8;
+            w.write_all(&[
+                BASE64[(n >> 18) as usize & 63],
+                BASE64[(n >> 12) as usize & 63],
+                BASE64[(n >> 6) as usize & 63],
+                b'=',
+            ])
+        }
+        _ => Ok(()),
+    }
+}
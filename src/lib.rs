@@ -0,0 +1,30 @@
+//! The search engine behind the grepox binary.
+//!
+//! A [`Searcher`] walks an input line by line, asks a [`Matcher`] whether each line matches and
+//! hands the selected lines to a [`Sink`]. [`StandardSink`] is the printer grepox itself uses.
+//!
+//! ```no_run
+//! use std::{io, path::Path};
+//! use grepox::{LiteralMatcher, MatchOn, Searcher, StandardSink};
+//!
+//! let matcher = LiteralMatcher::new(["hello"], false, true, MatchOn::Anywhere).unwrap();
+//! let mut sink = StandardSink::new(io::stdout().lock());
+//! Searcher::new().search_path(&matcher, Path::new("file.txt"), &mut sink)?;
+//! # Ok::<(), io::Error>(())
+//! ```
+
+pub mod diff;
+pub mod ignore;
+pub mod json;
+pub mod matcher;
+pub mod parallel;
+pub mod searcher;
+pub mod sink;
+pub mod walk;
+
+pub use diff::DiffSink;
+pub use json::{JsonSink, Stats};
+pub use matcher::{LiteralMatcher, MatchOn, Matcher, RegexMatcher};
+pub use parallel::search_parallel;
+pub use searcher::{BinaryDetection, MmapChoice, Searcher};
+pub use sink::{Sink, SinkContext, SinkMatch, StandardSink};
@@ -1,19 +1,22 @@
 use crate::config::Config;
-use crate::trait_ext::*;
+use crate::trait_ext::FromBool;
 
-use bstr::{io::BufReadExt, ByteSlice};
 use config::Flags;
-use regex::bytes::{Regex, RegexBuilder};
+use grepox::{
+    search_parallel, BinaryDetection, LiteralMatcher, MatchOn, Matcher, MmapChoice, RegexMatcher,
+    Searcher,
+};
+use printer::Printer;
+use regex::bytes::RegexBuilder;
 use std::{
-    borrow::Cow,
-    fs,
-    io::{self, BufWriter, IsTerminal, Read, StdoutLock, Write},
-    os::unix::prelude::OsStrExt,
-    path::{Path, PathBuf},
-    process::{exit, ExitCode},
+    io::{self, BufWriter, IsTerminal, Write},
+    path::Path,
+    process::ExitCode,
+    thread,
 };
 
 mod config;
+mod printer;
 mod trait_ext;
 
 #[macro_export]
@@ -21,6 +24,7 @@ macro_rules! print_help {
     () => {{
         println!(
             r"Usage: grepox [OPTION]... QUERY [FILES]...
+  or:  grepox [OPTION]... -e QUERY... [FILES]...
 Search for QUERY in FILES.
 Example:
     # Finds the phrase 'hello world' case-insensitively in file1.txt
@@ -29,17 +33,61 @@ Example:
 
 Options:
 -i          Ignore case distinctions in QUERY
+-S          Smart case: ignore case unless QUERY has uppercase letters,
+            on by default if GREPOX_SMART_CASE=1, see --no-smart-case
 -n          Print line number with output lines
+-b          Print the byte offset of every output line, or of every match with -o
 -v          Invert match: select non-matching lines
+-o          Print only the matched parts of lines, one match per line
 -F          String searching, disables regex
--x          Only match whole lines, only works with -F
--w          Only match whole words, only works with -F
+-x          Only match whole lines
+-w          Only match whole words, delimited by anything but letters,
+            digits and underscores
+-e <PAT>    Search for PAT, can be repeated, all arguments are then FILES
+-f <FILE>   Search for every line of FILE, can be combined with -e
+-r <TMPL>   Print matching lines with every match replaced by TMPL,
+            $1 and ${{name}} refer to capture groups, $0 to the whole match
 -U          No unicode, can speed up regular expressions
+-a          Search binary files as if they were text, see also --text
+-l          Only print the names of files with a match
+-L          Only print the names of files without any match,
+            exits with 0 if any file is printed
 -q          Quiet, do not write to standard output.
             Exits immediately with 0 if any match is found
 -m=<NUM>    Stop after NUM matches
+-A=<NUM>    Print NUM lines of trailing context after matches
+-B=<NUM>    Print NUM lines of leading context before matches
+-C=<NUM>    Print NUM lines of context around matches
+-j=<NUM>    Search NUM files in parallel, defaults to the number of CPUs
 -c          Colorizes output
--h          Print this help and exit"
+-h          Print this help and exit
+
+--json           Print results as JSON Lines
+--multiline      Let matches span lines, e.g. with \n or \s in QUERY,
+                 and print every line a match touches
+--no-smart-case  Disable smart case when GREPOX_SMART_CASE is set
+--heading        Print the file name once above its matches, the default
+                 when writing to a terminal
+--no-heading     Print the file name in front of every line
+--column         Print the byte column of the first match of every line
+--char-column    Like --column, but count characters instead of bytes
+--vimgrep        Print every match as path:line:column:line
+--count          Print the number of matching lines of every file
+--count-matches  Print the number of matches of every file
+--count-total    Print the sum of all counts, combine with --count-matches
+                 to sum matches instead of lines
+--include-zero   Also print files without any match with --count
+--in-place       Replace matches in FILES with -r instead of printing them
+--backup         Keep a copy of every rewritten file as FILE.bak
+--diff           Print the changes -r would make as a unified diff,
+                 -C sets the lines of context, 3 by default
+--text           Search binary files as if they were text
+--mmap           Memory map every regular file
+--no-mmap        Never memory map files, large files are mapped by default
+--hidden         Search hidden files and directories
+--no-ignore      Do not respect .gitignore and .ignore files
+--no-ignore-vcs  Do not respect .gitignore files
+--no-ignore-dot  Do not respect .ignore files"
         );
         ::std::process::exit(1)
     }};
@@ -53,448 +101,193 @@ macro_rules! error {
     }};
 }
 
-#[derive(PartialEq, Clone, Copy)]
-enum MatchOn {
-    Anywhere,
-    Line,
-    Word,
-}
-
 fn grep(cfg: Config) -> ExitCode {
-    let mut flags @ Flags {
+    let Flags {
         case_insensitive,
         string_search,
         no_unicode,
-        list,
+        quiet,
+        multiline,
         ..
     } = cfg.flags;
-    flags.multiple_files = flags.multiple_files || cfg.filenames.len() > 1;
 
-    let mut total_matches: u32 = 0;
-    let query = cfg.query;
-    let max = cfg.max;
-    let has_max = max > 0;
-    let filenames = cfg.filenames;
-    let match_on = cfg.match_on;
-
-    let stdout = std::io::stdout();
-    let stdout = stdout.lock();
-    let is_tty = std::io::stdin().is_terminal();
-    let mut writer = BufWriter::with_capacity(16384, stdout);
-    let mut buf = Vec::new();
-
-    if string_search {
-        let query = if case_insensitive {
-            query.to_lowercase()
-        } else {
-            query
-        };
-        let query = &query.into_bytes();
-        if !is_tty {
-            let mut i = 0;
-            let filename = Path::new("stdin");
-            let _ = {
-                let stdin = io::stdin();
-                let mut stdin = stdin.lock();
-                stdin.for_byte_line_with_terminator(|line| {
-                    if has_max && total_matches >= max {
-                        return Ok(false);
-                    }
-
-                    if check_string(
-                        &mut buf,
-                        &mut writer,
-                        flags,
-                        match_on,
-                        i,
-                        line,
-                        filename,
-                        query,
-                    ) {
-                        if list {
-                            if let Err(e) = writer.write_all(b"stdin\n") {
-                                error!("Could not write filename: {e}")
-                            }
-                            return Ok(false);
-                        }
-                        total_matches += 1;
-                    }
-
-                    i += 1;
-                    Ok(true)
-                })
-            };
-            return ExitCode::from_bool(total_matches > 0);
-        }
-
-        if filenames.is_empty() {
-            error!("No files specified");
-        }
-
-        let mut reader = Vec::new();
-        for filename in &filenames {
-            let mut matches: u32 = 0;
-            read_file(&mut reader, filename);
-            let mut i = 0;
-
-            let _ = reader.as_slice().for_byte_line_with_terminator(|line| {
-                if has_max && matches >= max {
-                    return Ok(false);
-                }
-
-                if check_string(
-                    &mut buf,
-                    &mut writer,
-                    flags,
-                    match_on,
-                    i,
-                    line,
-                    filename,
-                    query,
-                ) {
-                    if list {
-                        if let Err(e) = writer
-                            .write_all(filename.as_os_str().as_bytes())
-                            .and_then(|_| writer.write_all(b"\n"))
-                        {
-                            error!("Could not write filename: {e}")
-                        }
-                        return Ok(false);
-                    }
-                    matches += 1;
-                    total_matches += 1;
-                }
+    if multiline && cfg.replace.is_some() {
+        error!("--multiline cannot be combined with -r");
+    }
 
-                i += 1;
-                Ok(true)
-            });
+    // Literal needles are searched line by line, multiline searches go through the regex engine
+    let matcher: Box<dyn Matcher + Send + Sync> = if string_search && !multiline {
+        match LiteralMatcher::new(&cfg.patterns, case_insensitive, !no_unicode, cfg.match_on) {
+            Err(err) => error!("Error building literal matcher: {}", err),
+            Ok(matcher) => Box::new(matcher),
         }
     } else {
+        let patterns = cfg
+            .patterns
+            .iter()
+            .map(|pattern| match string_search {
+                true => regex::escape(pattern),
+                false => pattern.clone(),
+            })
+            .collect::<Vec<_>>();
+        let query = match patterns.as_slice() {
+            [pattern] => pattern.clone(),
+            patterns => patterns
+                .iter()
+                .map(|pattern| format!("(?:{pattern})"))
+                .collect::<Vec<_>>()
+                .join("|"),
+        };
+        // Half boundaries only look at one side, so patterns starting or ending in non-word
+        // characters still match, as with the literal matcher
+        let query = match cfg.match_on {
+            MatchOn::Anywhere => query,
+            MatchOn::Line => format!("^(?:{query})$"),
+            MatchOn::Word => format!(r"\b{{start-half}}(?:{query})\b{{end-half}}"),
+        };
         let re = RegexBuilder::new(&query)
             .unicode(!no_unicode)
             .case_insensitive(case_insensitive)
             .multi_line(true)
             .build();
 
-        let re = &match re {
+        match re {
             Err(err) => error!("Error parsing regex: {}", err),
-            Ok(re) => re,
-        };
-
-        if !is_tty {
-            let mut i = 0;
-            let filename = Path::new("stdin");
-            let _ = {
-                let stdin = io::stdin();
-                let mut stdin = stdin.lock();
-                stdin.for_byte_line_with_terminator(|line| {
-                    if has_max && total_matches >= max {
-                        return Ok(false);
-                    }
-
-                    if check_regex(&mut buf, &mut writer, flags, i, line, filename, re) {
-                        if list {
-                            if let Err(e) = writer.write_all(b"stdin\n") {
-                                error!("Could not write filename: {e}")
-                            }
-                            return Ok(false);
-                        }
-                        total_matches += 1;
-                    }
-
-                    i += 1;
-                    Ok(true)
-                })
-            };
-            return ExitCode::from_bool(total_matches > 0);
+            Ok(re) => Box::new(RegexMatcher::new(re)),
         }
+    };
 
-        if filenames.is_empty() {
-            error!("No files specified");
+    let mut searcher = Searcher::new();
+    searcher.invert = cfg.flags.invert;
+    searcher.max_count = cfg.max;
+    searcher.after_context = cfg.after_context;
+    searcher.before_context = cfg.before_context;
+    searcher.replace = cfg.replace.clone();
+    searcher.multiline = multiline;
+    if cfg.flags.diff {
+        if cfg.replace.is_none() {
+            error!("--diff requires -r");
         }
-
-        let mut reader = Vec::new();
-        for filename in &filenames {
-            let mut matches: u32 = 0;
-            read_file(&mut reader, filename);
-            let mut i = 0;
-
-            let _ = reader.as_slice().for_byte_line_with_terminator(|line| {
-                if has_max && matches >= max {
-                    return Ok(false);
-                }
-
-                if check_regex(&mut buf, &mut writer, flags, i, line, filename, re) {
-                    if list {
-                        if let Err(e) = writer
-                            .write_all(filename.as_os_str().as_bytes())
-                            .and_then(|_| writer.write_all(b"\n"))
-                        {
-                            error!("Could not write filename: {e}")
-                        }
-                        return Ok(false);
-                    }
-                    matches += 1;
-                    total_matches += 1;
-                }
-
-                i += 1;
-                Ok(true)
-            });
+        if cfg.flags.in_place {
+            error!("--diff cannot be combined with --in-place");
         }
+        // Hunks get the usual 3 lines of context unless asked otherwise
+        let context = match cfg.after_context.max(cfg.before_context) {
+            0 => 3,
+            n => n,
+        };
+        searcher.after_context = context;
+        searcher.before_context = context;
     }
-    writer.flush().unwrap();
-    ExitCode::from_bool(total_matches > 0)
-}
-
-fn print_match(
-    writer: &mut BufWriter<StdoutLock>,
-    index: usize,
-    line: &[u8],
-    show_lines: bool,
-    filename: &Path,
-    multiple_files: bool,
-) {
-    let res = if multiple_files {
-        writer
-            .write_all(filename.as_os_str().as_bytes())
-            .and_then(|_| {
-                if show_lines {
-                    write!(writer, ":{}:", index + 1)
-                } else {
-                    writer.write_all(b":")
-                }
-            })
-    } else if show_lines {
-        write!(writer, "{}:", index + 1)
-    } else {
-        Ok(())
+    // Binary files are skipped in recursive searches, but -L has to know whether they match.
+    // Counts print no lines, so binary files named on the command line are counted in full
+    searcher.binary = match (cfg.flags.text, cfg.recursive && !cfg.flags.list_without) {
+        (true, _) => BinaryDetection::None,
+        (false, true) => BinaryDetection::Quit,
+        (false, false) if cfg.flags.count && !cfg.flags.json => BinaryDetection::None,
+        (false, false) => BinaryDetection::Report,
+    };
+    searcher.mmap = match (cfg.flags.mmap, cfg.flags.no_mmap) {
+        (true, _) => MmapChoice::Always,
+        (_, true) => MmapChoice::Never,
+        _ => MmapChoice::Auto,
     };
-    if let Err(e) = res.and_then(|_| writer.write_all(line)) {
-        error!("Error writing to stdout: {}", e);
-    }
-}
-
-fn read_file(buf: &mut Vec<u8>, filename: &PathBuf) {
-    let mut file = fs::File::options()
-        .read(true)
-        .open(filename)
-        .unwrap_or_else(|e| error!("Error reading file: {}", e));
-
-    let needed = file.metadata().map(|m| m.len()).unwrap_or(0);
-    let needed: usize = needed
-        .try_into()
-        .unwrap_or_else(|_| error!("File too big: {}", needed));
 
-    buf.clear();
-    if buf.reserve_total(needed).is_err() {
-        error!("Could not allocate {needed} bytes");
+    if cfg.flags.in_place {
+        return rewrite(&cfg, searcher, &matcher);
     }
 
-    if let Err(e) = file.read_to_end(buf) {
-        error!("Error reading file: {}", e);
-    }
-}
+    let stdout = io::stdout();
+    let mut sink = Printer::new(&cfg, BufWriter::with_capacity(16384, stdout.lock()));
 
-#[allow(clippy::too_many_arguments)]
-fn check_string(
-    buf: &mut Vec<u8>,
-    writer: &mut BufWriter<StdoutLock>,
-    flags: Flags,
-    match_on: MatchOn,
-    i: usize,
-    line: &[u8],
-    source: &Path,
-    pattern: &[u8],
-) -> bool {
-    let line = if flags.case_insensitive {
-        Cow::Owned(line.to_ascii_lowercase())
-    } else {
-        Cow::Borrowed(line)
+    let threads = match cfg.threads {
+        0 => thread::available_parallelism().map_or(1, usize::from),
+        n => n as usize,
     };
 
-    match (match_on, !flags.color || flags.invert) {
-        (_, true) | (MatchOn::Line, _) => {
-            match match_on {
-                MatchOn::Anywhere => {
-                    if !line.contains_str(pattern) ^ flags.invert {
-                        return false;
-                    }
-                }
-                MatchOn::Line => {
-                    if (line != pattern) ^ flags.invert {
-                        return false;
-                    }
-                }
-                MatchOn::Word => {
-                    if line.words().all(|word| (word != pattern) ^ flags.invert) {
-                        return false;
-                    }
-                }
-            }
+    // An input counts towards the exit code if it has a match, or none with -L
+    let is_hit = |matches: u32| (matches > 0) != cfg.flags.list_without;
+    let mut hits: u32 = 0;
+    if cfg.filenames.is_empty() {
+        if io::stdin().is_terminal() {
+            error!("No files specified");
+        }
 
-            if flags.quiet {
-                exit(0);
-            }
-            if !flags.list {
-                print_match(
-                    writer,
-                    i,
-                    &line,
-                    flags.show_lines,
-                    source,
-                    flags.multiple_files,
-                );
-            }
-            return true;
+        let stdin = io::stdin();
+        match searcher.search_reader(&matcher, Path::new("stdin"), stdin.lock(), &mut sink) {
+            Ok(matches) => hits += is_hit(matches) as u32,
+            Err(e) => error!("Error writing to stdout: {}", e),
         }
-        (MatchOn::Anywhere, _) => {
-            let line = &*line;
-            let indices = line.find_iter(pattern).collect::<Vec<_>>();
-            if indices.is_empty() {
-                return false;
-            } else {
-                if flags.quiet {
-                    exit(0);
+    } else if threads > 1 && cfg.filenames.len() > 1 {
+        search_parallel(
+            &searcher,
+            &*matcher,
+            &cfg.filenames,
+            threads,
+            || Printer::new(&cfg, Vec::new()),
+            |_, file_sink, res| {
+                match res {
+                    Ok(matches) => hits += is_hit(matches) as u32,
+                    Err(e) => error!("Error reading file: {}", e),
                 }
-                if flags.list {
-                    return true;
+                if let Err(e) = sink.write_output(file_sink) {
+                    error!("Error writing to stdout: {}", e);
                 }
+                !(quiet && hits > 0)
+            },
+        );
+    } else {
+        for filename in &cfg.filenames {
+            match searcher.search_path(&matcher, filename, &mut sink) {
+                Ok(matches) => hits += is_hit(matches) as u32,
+                Err(e) => error!("Error reading file: {}", e),
             }
 
-            let needed = line.len() + indices.len() * 10;
-            if buf.reserve_total(needed).is_err() {
-                error!("Could not allocate {needed} bytes");
-            }
-            buf.clear();
-            let mut last = 0;
-            let len = pattern.len();
-
-            unsafe {
-                for idx in indices.into_iter() {
-                    buf.extend_from_slice_unchecked(&line[last..idx]);
-                    buf.extend_from_slice_unchecked(b"\x1b[31;1m");
-                    buf.extend_from_slice_unchecked(pattern);
-                    buf.extend_from_slice_unchecked(b"\x1b[m");
-                    last = idx + len;
-                }
-                buf.extend_from_slice_unchecked(&line[last..]);
+            if quiet && hits > 0 {
+                break;
             }
-
-            print_match(
-                writer,
-                i,
-                buf,
-                flags.show_lines,
-                source,
-                flags.multiple_files,
-            );
         }
-        (MatchOn::Word, _) => {
-            buf.clear();
-            let mut found = false;
-            for word in line.words() {
-                if word == pattern {
-                    if flags.quiet {
-                        exit(0);
-                    }
-                    if flags.list {
-                        return true;
-                    }
-                    found = true;
-                    let _ = buf
-                        .write_all(b"\x1b[31;1m")
-                        .and_then(|_| buf.write_all(word))
-                        .and_then(|_| buf.write_all(b"\x1b[m "));
-                } else {
-                    let _ = buf.write_all(word).map(|_| buf.push(b' '));
-                }
-            }
-            if !found {
-                return false;
-            }
-            buf.push(b'\n');
-            print_match(
-                writer,
-                i,
-                buf,
-                flags.show_lines,
-                source,
-                flags.multiple_files,
-            );
-        }
-    };
-    true
-}
+    }
 
-#[allow(clippy::too_many_arguments)]
-fn check_regex(
-    buf: &mut Vec<u8>,
-    writer: &mut BufWriter<StdoutLock>,
-    flags: Flags,
-    i: usize,
-    line: &[u8],
-    source: &Path,
-    pattern: &Regex,
-) -> bool {
-    if pattern.is_match(line) ^ flags.invert {
-        if flags.quiet {
-            exit(0);
-        }
-        if flags.list {
-            return true;
-        }
+    if let Err(e) = sink.finish_all().and_then(|_| sink.get_mut().flush()) {
+        error!("Error writing to stdout: {}", e);
     }
-    if flags.color && !flags.invert {
-        let indices: Vec<(usize, usize)> = pattern
-            .find_iter(line)
-            .map(|loc| (loc.start(), loc.end()))
-            .collect();
-        if indices.is_empty() {
-            return false;
-        }
+    ExitCode::from_bool(hits > 0)
+}
 
-        let colored = buf;
-        let needed = line.len() + indices.len() * 10;
-        if colored.reserve_total(needed).is_err() {
-            error!("Could not allocate {needed} bytes");
-        }
-        colored.clear();
+/// Replaces matches in every file instead of printing them, for `--in-place`.
+fn rewrite(cfg: &Config, mut searcher: Searcher, matcher: &impl Matcher) -> ExitCode {
+    if cfg.replace.is_none() {
+        error!("--in-place requires -r");
+    }
+    if cfg.filenames.is_empty() {
+        error!("--in-place requires FILES");
+    }
 
-        let mut last = 0;
-        unsafe {
-            for (start, end) in indices.into_iter() {
-                colored.extend_from_slice_unchecked(&line[last..start]);
-                colored.extend_from_slice_unchecked(b"\x1b[31;1m");
-                colored.extend_from_slice_unchecked(&line[start..end]);
-                colored.extend_from_slice_unchecked(b"\x1b[m");
-                last = end
+    let mut stdout = io::stdout().lock();
+    let mut total: u64 = 0;
+    let mut failed = false;
+    for filename in &cfg.filenames {
+        match searcher.rewrite_path(matcher, filename, cfg.flags.backup) {
+            Ok(0) => (),
+            Ok(n) => {
+                total += n;
+                if !cfg.flags.quiet {
+                    let _ = writeln!(stdout, "{}: {} replacements", filename.display(), n);
+                }
+            }
+            Err(e) => {
+                failed = true;
+                eprintln!("Error rewriting {}: {}", filename.display(), e);
             }
-            colored.extend_from_slice_unchecked(&line[last..]);
         }
-
-        print_match(
-            writer,
-            i,
-            colored,
-            flags.show_lines,
-            source,
-            flags.multiple_files,
-        );
-        return true;
     }
-    if pattern.is_match(line) ^ flags.invert {
-        print_match(
-            writer,
-            i,
-            line,
-            flags.show_lines,
-            source,
-            flags.multiple_files,
-        );
-        return true;
+
+    // Like grep, 2 reports an error even if other files were rewritten
+    if failed {
+        return ExitCode::from(2);
     }
-    false
+    ExitCode::from_bool(total > 0)
 }
 
 fn main() -> ExitCode {
@@ -0,0 +1,508 @@
+use std::{error, fmt, ops::Range};
+
+use aho_corasick::{AhoCorasick, Input, MatchKind};
+use bstr::{ByteSlice, Finder};
+use regex::bytes::Regex;
+use regex_automata::{meta, util::syntax, Anchored, PatternID};
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum MatchOn {
+    Anywhere,
+    Line,
+    Word,
+}
+
+/// Finds occurrences of a pattern inside a single line.
+///
+/// Lines are handed to the matcher without their terminator.
+pub trait Matcher {
+    /// Returns the byte range of the first match in `haystack` starting at or after `at`.
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Range<usize>>;
+
+    #[inline]
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        self.find_at(haystack, 0).is_some()
+    }
+
+    /// Pushes the byte ranges of every non-overlapping match in `haystack` onto `out`.
+    fn find_all(&self, haystack: &[u8], out: &mut Vec<Range<usize>>) {
+        let mut at = 0;
+        while at <= haystack.len() {
+            let Some(m) = self.find_at(haystack, at) else {
+                break;
+            };
+            at = if m.is_empty() { m.end + 1 } else { m.end };
+            out.push(m);
+        }
+    }
+
+    /// Appends `haystack` to `out` with every match replaced by `replacement` and returns the
+    /// number of replaced matches.
+    ///
+    /// `$0` or `${0}` in `replacement` stands for the whole match and `$$` for a literal `$`.
+    /// Matchers with capture groups also expand `$N` and `${name}`, other references expand
+    /// to nothing.
+    fn replace_all(&self, haystack: &[u8], replacement: &[u8], out: &mut Vec<u8>) -> usize {
+        let mut matches = Vec::new();
+        self.find_all(haystack, &mut matches);
+
+        let mut last = 0;
+        for m in &matches {
+            out.extend_from_slice(&haystack[last..m.start]);
+            expand(&haystack[m.clone()], replacement, out);
+            last = m.end;
+        }
+        out.extend_from_slice(&haystack[last..]);
+        matches.len()
+    }
+}
+
+/// Expands `replacement` for a match without capture groups.
+fn expand(whole: &[u8], replacement: &[u8], out: &mut Vec<u8>) {
+    let is_name = |b: &u8| b.is_ascii_alphanumeric() || *b == b'_';
+
+    let mut rest = replacement;
+    while let Some(i) = rest.iter().position(|&b| b == b'$') {
+        out.extend_from_slice(&rest[..i]);
+        rest = &rest[i + 1..];
+
+        let (name, len) = match rest {
+            [b'$', ..] => {
+                out.push(b'$');
+                rest = &rest[1..];
+                continue;
+            }
+            [b'{', tail @ ..] => match tail.iter().position(|&b| b == b'}') {
+                Some(end) => (&tail[..end], end + 2),
+                None => (&[][..], 0),
+            },
+            _ => {
+                let end = rest.iter().position(|b| !is_name(b)).unwrap_or(rest.len());
+                (&rest[..end], end)
+            }
+        };
+
+        if len == 0 || name.is_empty() {
+            out.push(b'$');
+            continue;
+        }
+        if name == b"0" {
+            out.extend_from_slice(whole);
+        }
+        rest = &rest[len..];
+    }
+    out.extend_from_slice(rest);
+}
+
+impl<M: Matcher + ?Sized> Matcher for &M {
+    #[inline]
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Range<usize>> {
+        (**self).find_at(haystack, at)
+    }
+
+    #[inline]
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        (**self).is_match(haystack)
+    }
+
+    #[inline]
+    fn find_all(&self, haystack: &[u8], out: &mut Vec<Range<usize>>) {
+        (**self).find_all(haystack, out)
+    }
+
+    #[inline]
+    fn replace_all(&self, haystack: &[u8], replacement: &[u8], out: &mut Vec<u8>) -> usize {
+        (**self).replace_all(haystack, replacement, out)
+    }
+}
+
+impl<M: Matcher + ?Sized> Matcher for Box<M> {
+    #[inline]
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Range<usize>> {
+        (**self).find_at(haystack, at)
+    }
+
+    #[inline]
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        (**self).is_match(haystack)
+    }
+
+    #[inline]
+    fn find_all(&self, haystack: &[u8], out: &mut Vec<Range<usize>>) {
+        (**self).find_all(haystack, out)
+    }
+
+    #[inline]
+    fn replace_all(&self, haystack: &[u8], replacement: &[u8], out: &mut Vec<u8>) -> usize {
+        (**self).replace_all(haystack, replacement, out)
+    }
+}
+
+/// How a [`LiteralMatcher`] looks for its needles.
+enum Needles {
+    /// A single needle, searched with a SIMD accelerated substring search.
+    One(Box<Finder<'static>>),
+    /// Any number of needles, or a single case-insensitive one, searched in a single pass over
+    /// the haystack.
+    ///
+    /// Uses leftmost-longest semantics for [`MatchOn::Anywhere`] and reports overlapping
+    /// matches otherwise, so a needle failing a word or line boundary does not hide a shorter
+    /// or longer one starting at the same position.
+    Many(AhoCorasick),
+    /// Needles compared with Unicode simple case folding, sorted longest first so the leftmost
+    /// match is also the longest. `order` maps the patterns back to the needle indices.
+    Folded { re: meta::Regex, order: Vec<usize> },
+}
+
+/// The error returned when the needles of a [`LiteralMatcher`] are too large to search for.
+#[derive(Debug)]
+pub enum BuildError {
+    AhoCorasick(aho_corasick::BuildError),
+    Folded(Box<meta::BuildError>),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AhoCorasick(err) => err.fmt(f),
+            Self::Folded(err) => err.fmt(f),
+        }
+    }
+}
+
+impl error::Error for BuildError {}
+
+impl From<aho_corasick::BuildError> for BuildError {
+    #[inline]
+    fn from(err: aho_corasick::BuildError) -> Self {
+        Self::AhoCorasick(err)
+    }
+}
+
+impl From<meta::BuildError> for BuildError {
+    #[inline]
+    fn from(err: meta::BuildError) -> Self {
+        Self::Folded(Box::new(err))
+    }
+}
+
+/// Plain substring search for one or more needles, used for `-F` and for queries without
+/// special characters.
+pub struct LiteralMatcher {
+    needles: Needles,
+    match_on: MatchOn,
+    unicode: bool,
+}
+
+impl LiteralMatcher {
+    /// With `unicode`, case-insensitive needles with non-ASCII letters use Unicode simple case
+    /// folding, so `Ä` matches `ä`, and non-ASCII letters and digits are word characters for
+    /// [`MatchOn::Word`]. Otherwise only ASCII is considered, which is faster.
+    pub fn new<I, B>(
+        needles: I,
+        case_insensitive: bool,
+        unicode: bool,
+        match_on: MatchOn,
+    ) -> Result<Self, BuildError>
+    where
+        I: IntoIterator<Item = B>,
+        B: AsRef<[u8]>,
+    {
+        let mut needles = needles.into_iter().collect::<Vec<_>>();
+        let folded = case_insensitive
+            && unicode
+            && needles.iter().any(|n| needs_unicode_folding(n.as_ref()))
+            && needles
+                .iter()
+                .all(|n| std::str::from_utf8(n.as_ref()).is_ok());
+
+        // The automaton folds ASCII case while searching, so lines never need to be lowercased
+        let needles = if folded {
+            Self::folded(&needles, match_on)?
+        } else if needles.len() == 1 && !case_insensitive {
+            Needles::One(Box::new(
+                Finder::new(needles.remove(0).as_ref()).into_owned(),
+            ))
+        } else {
+            let kind = match match_on {
+                MatchOn::Anywhere => MatchKind::LeftmostLongest,
+                MatchOn::Line | MatchOn::Word => MatchKind::Standard,
+            };
+            let ac = AhoCorasick::builder()
+                .match_kind(kind)
+                .ascii_case_insensitive(case_insensitive)
+                .build(needles)?;
+            Needles::Many(ac)
+        };
+
+        Ok(Self {
+            needles,
+            match_on,
+            unicode,
+        })
+    }
+
+    fn folded<B: AsRef<[u8]>>(needles: &[B], match_on: MatchOn) -> Result<Needles, BuildError> {
+        let mut order = (0..needles.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| usize::MAX - needles[i].as_ref().len());
+
+        let patterns = order
+            .iter()
+            .map(|&i| {
+                let needle = std::str::from_utf8(needles[i].as_ref()).expect("checked by caller");
+                let needle = regex::escape(needle);
+                match match_on {
+                    MatchOn::Line => format!("^(?:{needle})$"),
+                    MatchOn::Anywhere | MatchOn::Word => needle,
+                }
+            })
+            .collect::<Vec<_>>();
+        let re = meta::Regex::builder()
+            .syntax(syntax::Config::new().case_insensitive(true).utf8(false))
+            .build_many(&patterns)?;
+
+        Ok(Needles::Folded { re, order })
+    }
+
+    /// Like [`Matcher::find_at`], but also returns the index of the needle that matched.
+    pub fn find_pattern_at(&self, haystack: &[u8], at: usize) -> Option<(usize, Range<usize>)> {
+        match &self.needles {
+            Needles::One(needle) => self.find_one(needle, haystack, at).map(|m| (0, m)),
+            Needles::Many(ac) => self.find_many(ac, haystack, at),
+            Needles::Folded { re, order } => self.find_folded(re, order, haystack, at),
+        }
+    }
+
+    #[inline]
+    fn find_one(&self, needle: &Finder, haystack: &[u8], mut at: usize) -> Option<Range<usize>> {
+        let len = needle.needle().len();
+        match self.match_on {
+            MatchOn::Anywhere => {
+                let start = at + needle.find(haystack.get(at..)?)?;
+                Some(start..start + len)
+            }
+            MatchOn::Line => (at == 0 && haystack == needle.needle()).then_some(0..len),
+            MatchOn::Word => loop {
+                let start = at + needle.find(haystack.get(at..)?)?;
+                let end = start + len;
+                if is_word(haystack, start, end, self.unicode) {
+                    return Some(start..end);
+                }
+                at = start + 1;
+            },
+        }
+    }
+
+    #[inline]
+    fn find_many(
+        &self,
+        ac: &AhoCorasick,
+        haystack: &[u8],
+        at: usize,
+    ) -> Option<(usize, Range<usize>)> {
+        if at > haystack.len() {
+            return None;
+        }
+        let input = Input::new(haystack).span(at..haystack.len());
+
+        let bounded = |m: &aho_corasick::Match| match self.match_on {
+            MatchOn::Anywhere => true,
+            MatchOn::Line => m.start() == 0 && m.end() == haystack.len(),
+            MatchOn::Word => is_word(haystack, m.start(), m.end(), self.unicode),
+        };
+
+        if self.match_on == MatchOn::Anywhere {
+            let m = ac.find(input)?;
+            return Some((m.pattern().as_usize(), m.range()));
+        }
+
+        // Overlapping matches come ordered by their end, keep the leftmost-longest one and stop
+        // once no later match can start at or before it
+        let mut best: Option<aho_corasick::Match> = None;
+        for m in ac.find_overlapping_iter(input) {
+            if let Some(best) = &best {
+                if m.end().saturating_sub(ac.max_pattern_len()) > best.start() {
+                    break;
+                }
+            }
+            if bounded(&m)
+                && best.map_or(true, |best| {
+                    (m.start(), best.end()) < (best.start(), m.end())
+                })
+            {
+                best = Some(m);
+            }
+        }
+        best.map(|m| (m.pattern().as_usize(), m.range()))
+    }
+
+    #[inline]
+    fn find_folded(
+        &self,
+        re: &meta::Regex,
+        order: &[usize],
+        haystack: &[u8],
+        mut at: usize,
+    ) -> Option<(usize, Range<usize>)> {
+        loop {
+            if at > haystack.len() {
+                return None;
+            }
+            let input = regex_automata::Input::new(haystack).span(at..haystack.len());
+            let m = re.find(input)?;
+            if self.match_on != MatchOn::Word || is_word(haystack, m.start(), m.end(), self.unicode)
+            {
+                return Some((order[m.pattern().as_usize()], m.range()));
+            }
+
+            // Another needle starting at the same position may still be a whole word
+            let bounded = (0..re.pattern_len())
+                .filter_map(|pattern| {
+                    let input = regex_automata::Input::new(haystack)
+                        .span(m.start()..haystack.len())
+                        .anchored(Anchored::Pattern(PatternID::new_unchecked(pattern)));
+                    re.find(input)
+                })
+                .filter(|m| is_word(haystack, m.start(), m.end(), self.unicode))
+                .max_by_key(|m| m.end());
+            if let Some(m) = bounded {
+                return Some((order[m.pattern().as_usize()], m.range()));
+            }
+            at = m.start() + 1;
+        }
+    }
+}
+
+/// Whether `needle` has a letter that ASCII case folding does not handle.
+///
+/// ASCII needles stay with the much faster ASCII folding, even though under Unicode simple case
+/// folding `k` and `s` would also match the Kelvin sign and the long s.
+#[inline]
+fn needs_unicode_folding(needle: &[u8]) -> bool {
+    needle.chars().any(|c| !c.is_ascii() && c.is_alphabetic())
+}
+
+/// Whether `haystack[start..end]` is neither preceded nor followed by a letter, digit or
+/// underscore, so punctuation delimits words just like whitespace, as in `(foo),`.
+///
+/// Without `unicode` only ASCII letters and digits count, like `\b` in a regex built with `-U`.
+#[inline]
+fn is_word(haystack: &[u8], start: usize, end: usize, unicode: bool) -> bool {
+    if !unicode {
+        let is_word_byte =
+            |b: Option<&u8>| b.is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'_');
+        return !is_word_byte(haystack[..start].last()) && !is_word_byte(haystack.get(end));
+    }
+
+    let is_word_char = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let (before, _) = bstr::decode_last_utf8(&haystack[..start]);
+    let (after, _) = bstr::decode_utf8(&haystack[end..]);
+    !is_word_char(before) && !is_word_char(after)
+}
+
+impl Matcher for LiteralMatcher {
+    #[inline]
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Range<usize>> {
+        self.find_pattern_at(haystack, at).map(|(_, m)| m)
+    }
+
+    fn find_all(&self, haystack: &[u8], out: &mut Vec<Range<usize>>) {
+        let needle = match &self.needles {
+            Needles::One(needle) => needle,
+            Needles::Many(_) | Needles::Folded { .. } => {
+                let mut at = 0;
+                while let Some((_, m)) = self.find_pattern_at(haystack, at) {
+                    at = if m.is_empty() { m.end + 1 } else { m.end };
+                    out.push(m);
+                }
+                return;
+            }
+        };
+
+        let mut at = 0;
+        while let Some(m) = self.find_one(needle, haystack, at) {
+            at = if m.is_empty() { m.end + 1 } else { m.end };
+            out.push(m);
+        }
+    }
+}
+
+/// Regular expression search backed by [`regex::bytes::Regex`].
+pub struct RegexMatcher {
+    re: Regex,
+}
+
+impl RegexMatcher {
+    pub fn new(re: Regex) -> Self {
+        Self { re }
+    }
+}
+
+impl Matcher for RegexMatcher {
+    #[inline]
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Range<usize>> {
+        self.re.find_at(haystack, at).map(|m| m.range())
+    }
+
+    #[inline]
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        self.re.is_match(haystack)
+    }
+
+    fn find_all(&self, haystack: &[u8], out: &mut Vec<Range<usize>>) {
+        out.extend(self.re.find_iter(haystack).map(|m| m.range()));
+    }
+
+    fn replace_all(&self, haystack: &[u8], replacement: &[u8], out: &mut Vec<u8>) -> usize {
+        let mut last = 0;
+        let mut count = 0;
+        for caps in self.re.captures_iter(haystack) {
+            let m = caps.get(0).expect("group 0 always matches");
+            out.extend_from_slice(&haystack[last..m.start()]);
+            caps.expand(replacement, out);
+            last = m.end();
+            count += 1;
+        }
+        out.extend_from_slice(&haystack[last..]);
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LiteralMatcher, MatchOn, Matcher};
+
+    #[test]
+    fn folded_word_tries_every_needle_at_a_position() {
+        let matcher = LiteralMatcher::new(["äa-b", "äa"], true, true, MatchOn::Word).unwrap();
+        assert_eq!(matcher.find_at("ÄA-bc".as_bytes(), 0), Some(0..3));
+    }
+
+    #[test]
+    fn word_search_of_many_needles_is_linear() {
+        let line = "foo bar ".repeat(20_000);
+        let matcher = LiteralMatcher::new(["foo", "bar"], false, true, MatchOn::Word).unwrap();
+        let mut matches = Vec::new();
+        matcher.find_all(line.as_bytes(), &mut matches);
+        assert_eq!(matches.len(), 40_000);
+        assert_eq!(matches.last(), Some(&(159_996..159_999)));
+    }
+
+    #[test]
+    fn case_insensitive_word_search_is_linear() {
+        let line = "Foo bar ".repeat(20_000);
+        let matcher = LiteralMatcher::new(["foo"], true, true, MatchOn::Word).unwrap();
+        let mut matches = Vec::new();
+        matcher.find_all(line.as_bytes(), &mut matches);
+        assert_eq!(matches.len(), 20_000);
+    }
+
+    #[test]
+    fn ascii_word_bounds_ignore_non_ascii_letters() {
+        let haystack = "éfoo".as_bytes();
+        let ascii = LiteralMatcher::new(["foo"], false, false, MatchOn::Word).unwrap();
+        assert_eq!(ascii.find_at(haystack, 0), Some(2..5));
+        let unicode = LiteralMatcher::new(["foo"], false, true, MatchOn::Word).unwrap();
+        assert_eq!(unicode.find_at(haystack, 0), None);
+    }
+}
@@ -0,0 +1,73 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use crate::{matcher::Matcher, searcher::Searcher, sink::Sink};
+
+/// Searches `paths` on up to `threads` worker threads.
+///
+/// Every path is searched into a fresh sink from `make_sink`. Finished sinks are handed to `emit`
+/// together with their search result in the same order as `paths`, no matter which worker got
+/// to them first. Returning `false` from `emit` stops the search.
+pub fn search_parallel<M, S, F, E>(
+    searcher: &Searcher,
+    matcher: &M,
+    paths: &[PathBuf],
+    threads: usize,
+    make_sink: F,
+    mut emit: E,
+) where
+    M: Matcher + Sync + ?Sized,
+    S: Sink + Send,
+    F: Fn() -> S + Sync,
+    E: FnMut(&Path, S, io::Result<u32>) -> bool,
+{
+    let threads = threads.clamp(1, paths.len().max(1));
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (tx, rx) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let tx = tx.clone();
+            let mut searcher = searcher.clone();
+            let (next, stop, make_sink) = (&next, &stop, &make_sink);
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(path) = paths.get(i) else {
+                        break;
+                    };
+
+                    let mut sink = make_sink();
+                    let res = searcher.search_path(matcher, path, &mut sink);
+                    if tx.send((i, sink, res)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        // Results arrive in completion order, hold them back until every earlier path is out
+        let mut pending = BTreeMap::new();
+        let mut emitted = 0;
+        for (i, sink, res) in rx.iter() {
+            pending.insert(i, (sink, res));
+            while let Some((sink, res)) = pending.remove(&emitted) {
+                if !emit(&paths[emitted], sink, res) {
+                    stop.store(true, Ordering::Relaxed);
+                    return;
+                }
+                emitted += 1;
+            }
+        }
+    });
+}
@@ -0,0 +1,144 @@
+use std::{
+    io::{self, IsTerminal, Write},
+    path::Path,
+};
+
+use grepox::{DiffSink, JsonSink, Sink, SinkContext, SinkMatch, StandardSink};
+
+use crate::config::Config;
+
+/// The output format selected on the command line.
+pub enum Printer<W> {
+    Standard(StandardSink<W>),
+    Json(JsonSink<W>),
+    Diff(DiffSink<W>),
+}
+
+impl<W: Write> Printer<W> {
+    pub fn new(cfg: &Config, writer: W) -> Self {
+        if cfg.flags.diff && !cfg.flags.quiet {
+            return Self::Diff(DiffSink::new(writer));
+        }
+        if cfg.flags.json && !cfg.flags.quiet {
+            return Self::Json(JsonSink::new(writer));
+        }
+
+        let mut sink = StandardSink::new(writer);
+        sink.color = cfg.flags.color;
+        sink.line_number = cfg.flags.show_lines;
+        sink.with_filename = cfg.flags.multiple_files || cfg.filenames.len() > 1;
+        sink.heading = match (cfg.flags.heading, cfg.flags.no_heading) {
+            (true, _) => true,
+            (_, true) => false,
+            _ => io::stdout().is_terminal(),
+        };
+        sink.byte_offset = cfg.flags.byte_offset;
+        sink.column = cfg.flags.column;
+        sink.column_chars = cfg.flags.column_chars;
+        sink.vimgrep = cfg.flags.vimgrep;
+        sink.files_with_matches = cfg.flags.list;
+        sink.files_without_match = cfg.flags.list_without;
+        sink.quiet = cfg.flags.quiet;
+        sink.only_matching = cfg.flags.only_matching;
+        sink.count = cfg.flags.count;
+        sink.count_matches = cfg.flags.count_matches;
+        sink.count_total = cfg.flags.count_total;
+        sink.include_zero = cfg.flags.include_zero;
+        sink.group_separator =
+            !cfg.flags.only_matching && (cfg.after_context > 0 || cfg.before_context > 0);
+        Self::Standard(sink)
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut W {
+        match self {
+            Self::Standard(sink) => sink.get_mut(),
+            Self::Json(sink) => sink.get_mut(),
+            Self::Diff(sink) => sink.get_mut(),
+        }
+    }
+
+    /// Writes the output of a printer that searched a single input.
+    pub fn write_output(&mut self, other: Printer<Vec<u8>>) -> io::Result<()> {
+        match (self, other) {
+            (Self::Standard(sink), Printer::Standard(other)) => sink.write_output(other),
+            (Self::Json(sink), Printer::Json(other)) => sink.write_output(other),
+            (Self::Diff(sink), Printer::Diff(other)) => sink.write_output(other),
+            _ => unreachable!("printers are built from the same config"),
+        }
+    }
+
+    /// Writes whatever comes after the last input.
+    pub fn finish_all(&mut self) -> io::Result<()> {
+        match self {
+            Self::Standard(sink) => sink.write_total(),
+            Self::Diff(_) => Ok(()),
+            Self::Json(sink) => sink.write_summary(),
+        }
+    }
+}
+
+impl<W: Write> Sink for Printer<W> {
+    #[inline]
+    fn wants_ranges(&self) -> bool {
+        match self {
+            Self::Standard(sink) => sink.wants_ranges(),
+            Self::Json(sink) => sink.wants_ranges(),
+            Self::Diff(sink) => sink.wants_ranges(),
+        }
+    }
+
+    #[inline]
+    fn begin(&mut self, path: &Path) -> io::Result<()> {
+        match self {
+            Self::Standard(sink) => sink.begin(path),
+            Self::Json(sink) => sink.begin(path),
+            Self::Diff(sink) => sink.begin(path),
+        }
+    }
+
+    #[inline]
+    fn matched(&mut self, mat: &SinkMatch<'_>) -> io::Result<bool> {
+        match self {
+            Self::Standard(sink) => sink.matched(mat),
+            Self::Json(sink) => sink.matched(mat),
+            Self::Diff(sink) => sink.matched(mat),
+        }
+    }
+
+    #[inline]
+    fn context(&mut self, ctx: &SinkContext<'_>) -> io::Result<bool> {
+        match self {
+            Self::Standard(sink) => sink.context(ctx),
+            Self::Json(sink) => sink.context(ctx),
+            Self::Diff(sink) => sink.context(ctx),
+        }
+    }
+
+    #[inline]
+    fn binary_matched(&mut self, path: &Path) -> io::Result<()> {
+        match self {
+            Self::Standard(sink) => sink.binary_matched(path),
+            Self::Json(sink) => sink.binary_matched(path),
+            Self::Diff(sink) => sink.binary_matched(path),
+        }
+    }
+
+    #[inline]
+    fn context_break(&mut self) -> io::Result<()> {
+        match self {
+            Self::Standard(sink) => sink.context_break(),
+            Self::Json(sink) => sink.context_break(),
+            Self::Diff(sink) => sink.context_break(),
+        }
+    }
+
+    #[inline]
+    fn finish(&mut self, path: &Path, matches: u32) -> io::Result<()> {
+        match self {
+            Self::Standard(sink) => sink.finish(path, matches),
+            Self::Json(sink) => sink.finish(path, matches),
+            Self::Diff(sink) => sink.finish(path, matches),
+        }
+    }
+}
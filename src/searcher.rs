@@ -0,0 +1,468 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use bstr::{io::BufReadExt, ByteSlice};
+use memmap2::{Advice, Mmap};
+
+use crate::{
+    matcher::Matcher,
+    sink::{Sink, SinkContext, SinkMatch},
+};
+
+/// Capacity of the rolling buffer files are streamed through.
+const STREAM_BUFFER: usize = 65536;
+
+/// Size from which regular files are memory mapped with [`MmapChoice::Auto`].
+const MMAP_THRESHOLD: u64 = 1 << 24;
+
+/// How many leading bytes of an input are inspected to decide whether it is binary.
+const BINARY_BLOCK: usize = STREAM_BUFFER;
+
+/// What a [`Searcher`] does with inputs that contain a NUL byte in their first block.
+#[derive(Default, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum BinaryDetection {
+    /// Search every input as text.
+    None,
+    /// Skip binary inputs without reporting anything.
+    Quit,
+    /// Report the first selected line of a binary input through [`Sink::binary_matched`] and
+    /// stop searching it.
+    #[default]
+    Report,
+}
+
+/// Whether [`Searcher::search_path`] memory maps files instead of streaming them.
+///
+/// Only regular files whose size does not change while being mapped are ever mapped, everything
+/// else (pipes, `/proc` files, files being written to) falls back to buffered reads.
+#[derive(Default, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum MmapChoice {
+    /// Always stream.
+    Never,
+    /// Map regular files larger than 16 MiB.
+    #[default]
+    Auto,
+    /// Map every regular file that is not empty.
+    Always,
+}
+
+/// Drives a [`Matcher`] over an input line by line and reports selected lines to a [`Sink`].
+#[derive(Default, Clone)]
+pub struct Searcher {
+    /// Select lines that do not match instead.
+    pub invert: bool,
+    /// Stop searching an input after this many selected lines, 0 means no limit.
+    pub max_count: u32,
+    /// Number of lines to report after each selected line.
+    pub after_context: u32,
+    /// Number of lines to report before each selected line.
+    pub before_context: u32,
+    /// How inputs that look binary are handled.
+    pub binary: BinaryDetection,
+    /// When files are memory mapped instead of streamed.
+    pub mmap: MmapChoice,
+    /// Replace every match in selected lines with this template, see [`Matcher::replace_all`].
+    pub replace: Option<Vec<u8>>,
+    /// Run the matcher over the whole input instead of line by line, so matches can span
+    /// several lines. Every line a match touches is selected. Streamed inputs are read into
+    /// memory first and [`Searcher::replace`] is not applied.
+    pub multiline: bool,
+    ranges: Vec<Range<usize>>,
+    spans: Vec<Range<usize>>,
+    replaced: Vec<u8>,
+    before_lines: VecDeque<(u64, u64, Vec<u8>)>,
+}
+
+impl Searcher {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Streams the file at `path` through a fixed-size buffer and searches it.
+    ///
+    /// Memory use is bounded by the buffer size and the longest line, not by the size of the
+    /// file. Lines spanning two reads are stitched back together before being searched.
+    ///
+    /// Depending on [`Searcher::mmap`], the file may be memory mapped and searched in place
+    /// instead.
+    ///
+    /// Returns the number of selected lines.
+    pub fn search_path<M, S>(&mut self, matcher: M, path: &Path, sink: S) -> io::Result<u32>
+    where
+        M: Matcher,
+        S: Sink,
+    {
+        let file = fs::File::open(path)?;
+        if let Some(map) = self.map_file(&file) {
+            return self.search_slice(matcher, path, &map, sink);
+        }
+
+        let reader = BufReader::with_capacity(STREAM_BUFFER, file);
+        self.search_reader(matcher, path, reader, sink)
+    }
+
+    /// Rewrites the file at `path` with every match replaced by [`Searcher::replace`].
+    ///
+    /// The new contents go to a temporary file next to the original, which is synced and then
+    /// renamed over it, so readers never see a half-written file. Permissions are carried over
+    /// and with `backup` the original is first copied to `path.bak`. Files without any match are
+    /// left untouched, as are binary files unless [`Searcher::binary`] is
+    /// [`BinaryDetection::None`].
+    ///
+    /// Returns the number of substitutions made.
+    pub fn rewrite_path<M>(&mut self, matcher: M, path: &Path, backup: bool) -> io::Result<u64>
+    where
+        M: Matcher,
+    {
+        let Some(replace) = self.replace.as_deref() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No replacement given",
+            ));
+        };
+
+        // Write next to the file a symlink points to instead of replacing the link itself
+        let path = fs::canonicalize(path)?;
+        let file = fs::File::open(&path)?;
+        let permissions = file.metadata()?.permissions();
+        let mut reader = BufReader::with_capacity(STREAM_BUFFER, file);
+        if self.binary != BinaryDetection::None {
+            let block = reader.fill_buf()?;
+            if block[..block.len().min(BINARY_BLOCK)]
+                .find_byte(0)
+                .is_some()
+            {
+                return Ok(0);
+            }
+        }
+
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(format!(".grepox-{}.tmp", std::process::id()));
+        let tmp_path = PathBuf::from(tmp_path);
+
+        // The temporary file is only created at the first substitution, by copying everything
+        // before that line, so files without a match are never written to
+        let mut writer: Option<BufWriter<fs::File>> = None;
+        let replaced = &mut self.replaced;
+        let mut substitutions = 0;
+        let mut offset = 0;
+        let mut created = false;
+        let res = reader
+            .for_byte_line_with_terminator(|line| {
+                let content = line.strip_suffix(b"\n").unwrap_or(line);
+                replaced.clear();
+                let n = matcher.replace_all(content, replace, replaced);
+                let w = match &mut writer {
+                    Some(w) => w,
+                    None if n == 0 => {
+                        offset += line.len() as u64;
+                        return Ok(true);
+                    }
+                    None => {
+                        let tmp = fs::OpenOptions::new()
+                            .write(true)
+                            .create_new(true)
+                            .open(&tmp_path)?;
+                        created = true;
+                        let w = writer.insert(BufWriter::with_capacity(STREAM_BUFFER, tmp));
+                        io::copy(&mut fs::File::open(&path)?.take(offset), w)?;
+                        w
+                    }
+                };
+
+                if n == 0 {
+                    w.write_all(line)?;
+                } else {
+                    substitutions += n as u64;
+                    w.write_all(replaced)?;
+                    w.write_all(&line[content.len()..])?;
+                }
+                Ok(true)
+            })
+            .and_then(|_| {
+                let Some(writer) = writer else {
+                    return Ok(());
+                };
+                let tmp = writer.into_inner().map_err(|e| e.into_error())?;
+                tmp.set_permissions(permissions)?;
+                tmp.sync_all()?;
+
+                if backup {
+                    let mut backup = path.clone().into_os_string();
+                    backup.push(".bak");
+                    fs::copy(&path, backup)?;
+                }
+                fs::rename(&tmp_path, &path)
+            });
+
+        if res.is_err() && created {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        res.map(|_| substitutions)
+    }
+
+    fn map_file(&self, file: &fs::File) -> Option<Mmap> {
+        let md = file.metadata().ok()?;
+        let len = md.len();
+        match self.mmap {
+            _ if !md.is_file() || len == 0 => return None,
+            MmapChoice::Never => return None,
+            MmapChoice::Auto if len < MMAP_THRESHOLD => return None,
+            MmapChoice::Auto | MmapChoice::Always => (),
+        }
+
+        // SAFETY: Another process truncating the file while it is mapped makes reads fault.
+        // This is the same trade-off every mmap-based grep makes, and the size check below
+        // catches files that are actively being written to.
+        let map = unsafe { Mmap::map(file) }.ok()?;
+        if map.len() as u64 != len || file.metadata().ok()?.len() != len {
+            return None;
+        }
+        let _ = map.advise(Advice::Sequential);
+
+        Some(map)
+    }
+
+    /// Searches `slice` as if it were the contents of `path`.
+    ///
+    /// Returns the number of selected lines.
+    #[inline]
+    pub fn search_slice<M, S>(
+        &mut self,
+        matcher: M,
+        path: &Path,
+        slice: &[u8],
+        sink: S,
+    ) -> io::Result<u32>
+    where
+        M: Matcher,
+        S: Sink,
+    {
+        if !self.multiline {
+            return self.search_lines(matcher, path, slice, sink, false);
+        }
+
+        let mut spans = std::mem::take(&mut self.spans);
+        spans.clear();
+        matcher.find_all(slice, &mut spans);
+        self.spans = spans;
+        self.search_lines(matcher, path, slice, sink, true)
+    }
+
+    /// Searches everything readable from `reader`, labelling it as `path`.
+    ///
+    /// Returns the number of selected lines.
+    pub fn search_reader<M, R, S>(
+        &mut self,
+        matcher: M,
+        path: &Path,
+        mut reader: R,
+        sink: S,
+    ) -> io::Result<u32>
+    where
+        M: Matcher,
+        R: BufRead,
+        S: Sink,
+    {
+        if !self.multiline {
+            return self.search_lines(matcher, path, reader, sink, false);
+        }
+
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        self.search_slice(matcher, path, &buf, sink)
+    }
+
+    /// Searches `reader` line by line. With `multiline`, lines are selected by the matches in
+    /// [`Searcher::spans`] instead of by running the matcher on them.
+    fn search_lines<M, R, S>(
+        &mut self,
+        matcher: M,
+        path: &Path,
+        mut reader: R,
+        mut sink: S,
+        multiline: bool,
+    ) -> io::Result<u32>
+    where
+        M: Matcher,
+        R: BufRead,
+        S: Sink,
+    {
+        let is_binary = self.binary != BinaryDetection::None && {
+            let block = reader.fill_buf()?;
+            block[..block.len().min(BINARY_BLOCK)]
+                .find_byte(0)
+                .is_some()
+        };
+        if is_binary && self.binary == BinaryDetection::Quit {
+            return Ok(0);
+        }
+
+        let invert = self.invert;
+        let max = self.max_count;
+        let (before, after) = if is_binary {
+            (0, 0)
+        } else {
+            (self.before_context as usize, self.after_context as usize)
+        };
+        let has_context = before > 0 || after > 0;
+        let wants_ranges = sink.wants_ranges() && !invert;
+        let ranges = &mut self.ranges;
+        let spans = multiline.then_some(self.spans.as_slice());
+        let mut next_span = 0;
+        let replace = self.replace.as_deref().filter(|_| !multiline);
+        let replaced = &mut self.replaced;
+        let pending = &mut self.before_lines;
+        pending.clear();
+
+        let mut matches: u32 = 0;
+        let mut line_number = 0;
+        let mut offset = 0;
+        let mut last_emitted = 0;
+        let mut after_left = 0;
+
+        sink.begin(path)?;
+        reader.for_byte_line_with_terminator(|line| {
+            line_number += 1;
+            let absolute_offset = offset;
+            offset += line.len() as u64;
+
+            // Trailing context after the last allowed match is still printed
+            if max > 0 && matches >= max {
+                if after_left == 0 {
+                    return Ok(false);
+                }
+                after_left -= 1;
+                last_emitted = line_number;
+                return sink.context(&SinkContext {
+                    path,
+                    line_number,
+                    absolute_offset,
+                    line,
+                });
+            }
+
+            let content = line.strip_suffix(b"\n").unwrap_or(line);
+            ranges.clear();
+            let mut starts = None;
+            let is_match = if let Some(spans) = spans {
+                let start = absolute_offset as usize;
+                starts = spanned(spans, &mut next_span, start..start + content.len(), ranges);
+                starts.is_some()
+            } else if wants_ranges {
+                matcher.find_all(content, ranges);
+                !ranges.is_empty()
+            } else {
+                matcher.is_match(content)
+            };
+
+            if is_match == invert {
+                if after_left > 0 {
+                    after_left -= 1;
+                    last_emitted = line_number;
+                    return sink.context(&SinkContext {
+                        path,
+                        line_number,
+                        absolute_offset,
+                        line,
+                    });
+                }
+                if before > 0 {
+                    let mut buf = if pending.len() == before {
+                        pending
+                            .pop_front()
+                            .map(|(_, _, buf)| buf)
+                            .unwrap_or_default()
+                    } else {
+                        Vec::new()
+                    };
+                    buf.clear();
+                    buf.extend_from_slice(line);
+                    pending.push_back((line_number, absolute_offset, buf));
+                }
+                return Ok(true);
+            }
+
+            let first = pending.front().map_or(line_number, |(n, _, _)| *n);
+            if has_context && last_emitted > 0 && first > last_emitted + 1 {
+                sink.context_break()?;
+            }
+            for (n, off, buf) in pending.drain(..) {
+                let ctx = SinkContext {
+                    path,
+                    line_number: n,
+                    absolute_offset: off,
+                    line: &buf,
+                };
+                if !sink.context(&ctx)? {
+                    return Ok(false);
+                }
+            }
+
+            matches += 1;
+            if is_binary {
+                sink.binary_matched(path)?;
+                return Ok(false);
+            }
+
+            last_emitted = line_number;
+            after_left = after;
+            let replacement = replace.map(|replace| {
+                replaced.clear();
+                matcher.replace_all(content, replace, replaced);
+                replaced.extend_from_slice(&line[content.len()..]);
+                replaced.as_slice()
+            });
+            sink.matched(&SinkMatch {
+                path,
+                line_number,
+                absolute_offset,
+                line,
+                ranges,
+                match_count: starts.unwrap_or(ranges.len()),
+                replacement,
+            })
+        })?;
+        sink.finish(path, matches)?;
+
+        Ok(matches)
+    }
+}
+
+/// Pushes the parts of `spans` that touch `line` onto `ranges`, relative to the start of the line.
+///
+/// Returns `None` if no span touches the line, otherwise the number of spans starting in it, so
+/// a span covering several lines is only counted once.
+///
+/// A span touches a line if it overlaps it or its terminator, which sits at `line.end`. `spans`
+/// must be sorted and `next` is where the search for the previous line left off.
+fn spanned(
+    spans: &[Range<usize>],
+    next: &mut usize,
+    line: Range<usize>,
+    ranges: &mut Vec<Range<usize>>,
+) -> Option<usize> {
+    while spans
+        .get(*next)
+        .is_some_and(|s| s.end < line.start || (s.end == line.start && !s.is_empty()))
+    {
+        *next += 1;
+    }
+
+    let mut found = false;
+    let mut starts = 0;
+    for span in spans[*next..].iter().take_while(|s| s.start <= line.end) {
+        found = true;
+        starts += (span.start >= line.start) as usize;
+        let start = span.start.max(line.start) - line.start;
+        let end = span.end.clamp(line.start, line.end) - line.start;
+        ranges.push(start..end.max(start));
+    }
+    found.then_some(starts)
+}
@@ -0,0 +1,594 @@
+use std::{
+    io::{self, Write},
+    ops::Range,
+    os::unix::prelude::OsStrExt,
+    path::Path,
+    slice,
+};
+
+use bstr::ByteSlice;
+
+/// A line selected by the [`Searcher`](crate::Searcher).
+pub struct SinkMatch<'a> {
+    pub path: &'a Path,
+    /// 1-based line number.
+    pub line_number: u64,
+    /// Byte offset of the start of the line from the start of the input.
+    pub absolute_offset: u64,
+    /// The line, including its terminator if it had one.
+    pub line: &'a [u8],
+    /// Byte ranges of the matches inside `line`.
+    ///
+    /// Only filled in if [`Sink::wants_ranges`] returns true and the search is not inverted.
+    pub ranges: &'a [Range<usize>],
+    /// Number of matches starting in `line`, filled in along with `ranges`.
+    ///
+    /// Usually the length of `ranges`, but in [multiline](crate::Searcher::multiline) searches
+    /// a match continued from an earlier line has a range here without being counted again.
+    pub match_count: usize,
+    /// The line with every match replaced, if [`Searcher::replace`](crate::Searcher::replace)
+    /// is set.
+    pub replacement: Option<&'a [u8]>,
+}
+
+/// A line surrounding a selected line, reported when context is requested.
+pub struct SinkContext<'a> {
+    pub path: &'a Path,
+    /// 1-based line number.
+    pub line_number: u64,
+    /// Byte offset of the start of the line from the start of the input.
+    pub absolute_offset: u64,
+    /// The line, including its terminator if it had one.
+    pub line: &'a [u8],
+}
+
+/// Receives search events from a [`Searcher`](crate::Searcher).
+pub trait Sink {
+    /// Whether [`SinkMatch::ranges`] should be computed for every selected line.
+    #[inline]
+    fn wants_ranges(&self) -> bool {
+        false
+    }
+
+    /// Called before `path` is searched.
+    #[inline]
+    fn begin(&mut self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called for every selected line.
+    ///
+    /// Returning `Ok(false)` stops the search of the current input.
+    fn matched(&mut self, mat: &SinkMatch<'_>) -> io::Result<bool>;
+
+    /// Called for every context line.
+    ///
+    /// Returning `Ok(false)` stops the search of the current input.
+    #[inline]
+    fn context(&mut self, _ctx: &SinkContext<'_>) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    /// Called instead of [`Sink::matched`] when a line is selected in an input that looks binary.
+    /// The search of that input stops afterwards.
+    #[inline]
+    fn binary_matched(&mut self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called between two groups of reported lines that are not adjacent.
+    #[inline]
+    fn context_break(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called after `path` has been searched with the number of selected lines.
+    #[inline]
+    fn finish(&mut self, _path: &Path, _matches: u32) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for &mut S {
+    #[inline]
+    fn wants_ranges(&self) -> bool {
+        (**self).wants_ranges()
+    }
+
+    #[inline]
+    fn begin(&mut self, path: &Path) -> io::Result<()> {
+        (**self).begin(path)
+    }
+
+    #[inline]
+    fn matched(&mut self, mat: &SinkMatch<'_>) -> io::Result<bool> {
+        (**self).matched(mat)
+    }
+
+    #[inline]
+    fn context(&mut self, ctx: &SinkContext<'_>) -> io::Result<bool> {
+        (**self).context(ctx)
+    }
+
+    #[inline]
+    fn binary_matched(&mut self, path: &Path) -> io::Result<()> {
+        (**self).binary_matched(path)
+    }
+
+    #[inline]
+    fn context_break(&mut self) -> io::Result<()> {
+        (**self).context_break()
+    }
+
+    #[inline]
+    fn finish(&mut self, path: &Path, matches: u32) -> io::Result<()> {
+        (**self).finish(path, matches)
+    }
+}
+
+/// The `path:line:text` printer used by the grepox binary.
+pub struct StandardSink<W> {
+    writer: W,
+    /// Highlight matches with ANSI escapes.
+    pub color: bool,
+    /// Prefix every line with its line number.
+    pub line_number: bool,
+    /// Prefix every line with the path it was found in.
+    pub with_filename: bool,
+    /// Prefix every line with the byte offset of its start, or of the match with `only_matching`.
+    pub byte_offset: bool,
+    /// Prefix matches with the 1-based column of the first match after the line number.
+    pub column: bool,
+    /// Count `column` in characters instead of bytes.
+    pub column_chars: bool,
+    /// Print the whole line once for every match, prefixed with its column, the format of
+    /// vim's `:grep`. Implies `column`.
+    pub vimgrep: bool,
+    /// With `with_filename`, print the path once above the lines of every input instead of in
+    /// front of every line and separate inputs with a blank line.
+    pub heading: bool,
+    /// Only print the path of inputs with at least one selected line.
+    pub files_with_matches: bool,
+    /// Only print the path of inputs without any selected line.
+    pub files_without_match: bool,
+    /// Print nothing and stop at the first selected line.
+    pub quiet: bool,
+    /// Print every non-empty match on its own line instead of the whole line.
+    pub only_matching: bool,
+    /// Print the number of selected lines of every input instead of the lines themselves.
+    ///
+    /// The search of a binary input stops at its first selected line, which then counts as the
+    /// only one. Search with [`BinaryDetection::None`](crate::BinaryDetection::None) to count
+    /// every line of binary inputs.
+    pub count: bool,
+    /// With `count`, count individual matches instead of lines.
+    pub count_matches: bool,
+    /// With `count`, print a single sum over all inputs from [`StandardSink::write_total`].
+    pub count_total: bool,
+    /// With `count`, also print inputs with a count of zero.
+    pub include_zero: bool,
+    /// Print `--` between groups of lines that are not adjacent, including across inputs.
+    pub group_separator: bool,
+    printed: bool,
+    pending_break: bool,
+    pending_heading: bool,
+    file_count: u64,
+    total: u64,
+}
+
+impl<W: Write> StandardSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            color: false,
+            line_number: false,
+            with_filename: false,
+            byte_offset: false,
+            column: false,
+            column_chars: false,
+            vimgrep: false,
+            heading: false,
+            files_with_matches: false,
+            files_without_match: false,
+            quiet: false,
+            only_matching: false,
+            count: false,
+            count_matches: false,
+            count_total: false,
+            include_zero: false,
+            group_separator: false,
+            printed: false,
+            pending_break: false,
+            pending_heading: false,
+            file_count: 0,
+            total: 0,
+        }
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Writes the output of another `StandardSink` that searched a single input as if this sink
+    /// had produced it, keeping group separators between inputs and the count total intact.
+    pub fn write_output(&mut self, other: StandardSink<Vec<u8>>) -> io::Result<()> {
+        self.total += other.total;
+        // Only lines start a group, paths and counts of `-l`, `-L` and `--count` never do
+        if other.printed {
+            if self.printed && self.headings() {
+                self.writer.write_all(b"\n")?;
+            } else if self.printed && self.group_separator {
+                self.writer.write_all(b"--\n")?;
+            }
+            self.printed = true;
+        }
+        self.writer.write_all(&other.writer)
+    }
+
+    /// Writes the sum of all counts if `count_total` is set.
+    pub fn write_total(&mut self) -> io::Result<()> {
+        if self.count && self.count_total && !self.quiet {
+            writeln!(self.writer, "{}", self.total)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> StandardSink<W> {
+    /// Whether lines are grouped under headings, paths and counts are printed as they are.
+    #[inline]
+    fn headings(&self) -> bool {
+        self.heading
+            && self.with_filename
+            && !self.vimgrep
+            && !self.files_with_matches
+            && !self.files_without_match
+            && !self.count
+    }
+
+    /// Writes the blank line separating inputs in heading mode, and the heading itself unless
+    /// the path is printed anyway.
+    fn write_heading(&mut self, path: Option<&Path>) -> io::Result<()> {
+        self.pending_heading = false;
+        if self.printed {
+            self.writer.write_all(b"\n")?;
+        }
+        self.printed = true;
+        let Some(path) = path else {
+            return Ok(());
+        };
+
+        let path = path.as_os_str().as_bytes();
+        if self.color {
+            self.writer.write_all(b"\x1b[35m")?;
+            self.writer.write_all(path)?;
+            self.writer.write_all(b"\x1b[m\n")
+        } else {
+            self.writer.write_all(path)?;
+            self.writer.write_all(b"\n")
+        }
+    }
+
+    fn column_of(&self, line: &[u8], start: usize) -> Option<u64> {
+        if !self.column && !self.vimgrep {
+            return None;
+        }
+        let column = match self.column_chars {
+            true => line[..start].chars().count(),
+            false => start,
+        };
+        Some(column as u64 + 1)
+    }
+
+    fn write_prefix(
+        &mut self,
+        path: &Path,
+        line_number: u64,
+        offset: u64,
+        column: Option<u64>,
+        sep: u8,
+    ) -> io::Result<()> {
+        if self.pending_heading {
+            self.write_heading(Some(path))?;
+        }
+        let writer = &mut self.writer;
+        if self.pending_break {
+            writer.write_all(b"--\n")?;
+            self.pending_break = false;
+        }
+        self.printed = true;
+
+        if (self.with_filename && !self.heading) || self.vimgrep {
+            writer.write_all(path.as_os_str().as_bytes())?;
+            writer.write_all(&[sep])?;
+        }
+        if self.line_number || self.vimgrep {
+            write!(writer, "{}", line_number)?;
+            writer.write_all(&[sep])?;
+        }
+        if self.byte_offset {
+            write!(writer, "{}", offset)?;
+            writer.write_all(&[sep])?;
+        }
+        if let Some(column) = column {
+            write!(writer, "{}", column)?;
+            writer.write_all(&[sep])?;
+        }
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &[u8], ranges: &[Range<usize>]) -> io::Result<()> {
+        let writer = &mut self.writer;
+        let mut last = 0;
+        for range in ranges.iter().filter(|r| !r.is_empty()) {
+            writer.write_all(&line[last..range.start])?;
+            writer.write_all(b"\x1b[31;1m")?;
+            writer.write_all(&line[range.clone()])?;
+            writer.write_all(b"\x1b[m")?;
+            last = range.end;
+        }
+        writer.write_all(&line[last..])?;
+        if line.last() != Some(&b'\n') {
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Sink for StandardSink<W> {
+    #[inline]
+    fn wants_ranges(&self) -> bool {
+        let count = self.count && !self.count_matches;
+        (self.color || self.only_matching || self.column || self.vimgrep || self.count_matches)
+            && !self.quiet
+            && !self.files_with_matches
+            && !self.files_without_match
+            && !count
+    }
+
+    fn begin(&mut self, _path: &Path) -> io::Result<()> {
+        self.file_count = 0;
+        self.pending_heading = self.headings();
+        self.pending_break = self.group_separator && self.printed && !self.pending_heading;
+        Ok(())
+    }
+
+    fn matched(&mut self, mat: &SinkMatch<'_>) -> io::Result<bool> {
+        if self.quiet {
+            return Ok(false);
+        }
+
+        if self.files_with_matches {
+            self.writer.write_all(mat.path.as_os_str().as_bytes())?;
+            self.writer.write_all(b"\n")?;
+            return Ok(false);
+        }
+        if self.files_without_match {
+            return Ok(false);
+        }
+
+        if self.count {
+            // Inverted searches select lines without any match, count those as one
+            self.file_count += match self.count_matches {
+                true if mat.ranges.is_empty() => 1,
+                true => mat.match_count as u64,
+                false => 1,
+            };
+            return Ok(true);
+        }
+
+        if self.only_matching || (self.vimgrep && !mat.ranges.is_empty()) {
+            for range in mat.ranges.iter().filter(|r| !r.is_empty()) {
+                let column = self.column_of(mat.line, range.start);
+                let offset = mat.absolute_offset + range.start as u64;
+                self.write_prefix(mat.path, mat.line_number, offset, column, b':')?;
+                if self.only_matching {
+                    let whole = 0..range.len();
+                    let ranges = if self.color {
+                        slice::from_ref(&whole)
+                    } else {
+                        &[]
+                    };
+                    self.write_line(&mat.line[range.clone()], ranges)?;
+                } else {
+                    let ranges = if self.color {
+                        slice::from_ref(range)
+                    } else {
+                        &[]
+                    };
+                    self.write_line(mat.line, ranges)?;
+                }
+            }
+            return Ok(true);
+        }
+
+        let column = mat
+            .ranges
+            .first()
+            .and_then(|r| self.column_of(mat.line, r.start));
+        self.write_prefix(mat.path, mat.line_number, mat.absolute_offset, column, b':')?;
+        match mat.replacement {
+            Some(replaced) => self.write_line(replaced, &[])?,
+            None if self.color => self.write_line(mat.line, mat.ranges)?,
+            None => self.write_line(mat.line, &[])?,
+        }
+        Ok(true)
+    }
+
+    fn context(&mut self, ctx: &SinkContext<'_>) -> io::Result<bool> {
+        if self.quiet
+            || self.files_with_matches
+            || self.files_without_match
+            || self.only_matching
+            || self.count
+        {
+            return Ok(true);
+        }
+
+        self.write_prefix(ctx.path, ctx.line_number, ctx.absolute_offset, None, b'-')?;
+        self.write_line(ctx.line, &[])?;
+        Ok(true)
+    }
+
+    fn binary_matched(&mut self, path: &Path) -> io::Result<()> {
+        if self.quiet || self.files_without_match {
+            return Ok(());
+        }
+
+        let path = path.as_os_str().as_bytes();
+        if self.files_with_matches {
+            self.writer.write_all(path)?;
+            return self.writer.write_all(b"\n");
+        }
+        if self.count {
+            self.file_count += 1;
+            return Ok(());
+        }
+
+        if self.pending_heading {
+            self.write_heading(None)?;
+        }
+        if self.pending_break {
+            self.writer.write_all(b"--\n")?;
+            self.pending_break = false;
+        }
+        self.printed = true;
+        self.writer.write_all(b"Binary file ")?;
+        self.writer.write_all(path)?;
+        self.writer.write_all(b" matches\n")
+    }
+
+    fn context_break(&mut self) -> io::Result<()> {
+        self.pending_break = self.group_separator && !self.only_matching;
+        Ok(())
+    }
+
+    fn finish(&mut self, path: &Path, matches: u32) -> io::Result<()> {
+        if self.files_without_match && matches == 0 && !self.quiet {
+            self.writer.write_all(path.as_os_str().as_bytes())?;
+            return self.writer.write_all(b"\n");
+        }
+        if !self.count || self.quiet || self.files_with_matches {
+            return Ok(());
+        }
+
+        self.total += self.file_count;
+        if self.count_total || (self.file_count == 0 && !self.include_zero) {
+            return Ok(());
+        }
+        if self.with_filename {
+            self.writer.write_all(path.as_os_str().as_bytes())?;
+            self.writer.write_all(b":")?;
+        }
+        writeln!(self.writer, "{}", self.file_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use regex::bytes::Regex;
+
+    use super::StandardSink;
+    use crate::{RegexMatcher, Searcher};
+
+    const INPUTS: [(&str, &[u8]); 3] = [
+        ("a.txt", b"foo\nx\n"),
+        ("b.txt", b"foo\ny\n"),
+        ("c.txt", b"z\n"),
+    ];
+
+    /// Searches [`INPUTS`] into one sink and into a sink per input merged with
+    /// [`StandardSink::write_output`], and returns both outputs.
+    fn sequential_and_merged(configure: fn(&mut StandardSink<Vec<u8>>)) -> (String, String) {
+        let matcher = RegexMatcher::new(Regex::new("foo").unwrap());
+        let mut searcher = Searcher::new();
+        searcher.after_context = 1;
+
+        let mut sequential = StandardSink::new(Vec::new());
+        configure(&mut sequential);
+        let mut merged = StandardSink::new(Vec::new());
+        configure(&mut merged);
+        for (path, input) in INPUTS {
+            searcher
+                .search_slice(&matcher, Path::new(path), input, &mut sequential)
+                .unwrap();
+
+            let mut sink = StandardSink::new(Vec::new());
+            configure(&mut sink);
+            searcher
+                .search_slice(&matcher, Path::new(path), input, &mut sink)
+                .unwrap();
+            merged.write_output(sink).unwrap();
+        }
+
+        let output = |sink: StandardSink<Vec<u8>>| String::from_utf8(sink.into_inner()).unwrap();
+        (output(sequential), output(merged))
+    }
+
+    #[test]
+    fn merged_output_separates_only_lines() {
+        let (sequential, merged) = sequential_and_merged(|sink| {
+            sink.with_filename = true;
+            sink.group_separator = true;
+        });
+        assert_eq!(sequential, "a.txt:foo\na.txt-x\n--\nb.txt:foo\nb.txt-y\n");
+        assert_eq!(merged, sequential);
+
+        let (sequential, merged) = sequential_and_merged(|sink| {
+            sink.group_separator = true;
+            sink.files_with_matches = true;
+        });
+        assert_eq!(sequential, "a.txt\nb.txt\n");
+        assert_eq!(merged, sequential);
+
+        let (sequential, merged) = sequential_and_merged(|sink| {
+            sink.with_filename = true;
+            sink.group_separator = true;
+            sink.count = true;
+        });
+        assert_eq!(sequential, "a.txt:1\nb.txt:1\n");
+        assert_eq!(merged, sequential);
+    }
+
+    #[test]
+    fn merged_output_puts_blank_lines_only_between_headings() {
+        let (sequential, merged) = sequential_and_merged(|sink| {
+            sink.with_filename = true;
+            sink.heading = true;
+        });
+        assert_eq!(sequential, "a.txt\nfoo\nx\n\nb.txt\nfoo\ny\n");
+        assert_eq!(merged, sequential);
+
+        let (sequential, merged) = sequential_and_merged(|sink| {
+            sink.with_filename = true;
+            sink.heading = true;
+            sink.files_with_matches = true;
+        });
+        assert_eq!(sequential, "a.txt\nb.txt\n");
+        assert_eq!(merged, sequential);
+
+        let (sequential, merged) = sequential_and_merged(|sink| {
+            sink.with_filename = true;
+            sink.heading = true;
+            sink.files_without_match = true;
+        });
+        assert_eq!(sequential, "c.txt\n");
+        assert_eq!(merged, sequential);
+
+        let (sequential, merged) = sequential_and_merged(|sink| {
+            sink.with_filename = true;
+            sink.heading = true;
+            sink.count = true;
+        });
+        assert_eq!(sequential, "a.txt:1\nb.txt:1\n");
+        assert_eq!(merged, sequential);
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    os::unix::prelude::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use walkdir::{DirEntry, WalkDir};
+
+use crate::ignore::{Gitignore, Match};
+
+/// Controls which entries [`walk`] skips.
+///
+/// The default skips hidden entries and honours both `.gitignore` and `.ignore` files.
+#[derive(Default, Clone, Copy)]
+pub struct WalkOptions {
+    /// Yield and descend into entries whose name starts with a `.`.
+    pub hidden: bool,
+    /// Do not read `.gitignore` files.
+    pub no_ignore_vcs: bool,
+    /// Do not read `.ignore` files.
+    pub no_ignore_dot: bool,
+}
+
+/// The ignore files of the directories above an entry, with the depth of their directory.
+type IgnoreStack = Vec<(usize, Vec<Gitignore>)>;
+
+/// Recursively yields the regular files under `root`.
+///
+/// Ignore files apply to the directory they are found in and everything below it, rules from
+/// deeper files take precedence over shallower ones and `.ignore` takes precedence over
+/// `.gitignore`. Inside a git repository, the ignore files of the directories between the
+/// repository root and `root` apply as well. Ignored directories are not descended into. `root`
+/// itself is never skipped.
+pub fn walk(root: &Path, opts: WalkOptions) -> impl Iterator<Item = PathBuf> {
+    let mut stack: IgnoreStack = Vec::new();
+    let parents = parent_ignores(root, opts);
+    let root = root.to_path_buf();
+
+    WalkDir::new(&root)
+        .into_iter()
+        .filter_entry(move |entry| {
+            let depth = entry.depth();
+            while stack.last().is_some_and(|(d, _)| *d >= depth) {
+                stack.pop();
+            }
+
+            let is_dir = entry.file_type().is_dir();
+            if depth > 0 {
+                if !opts.hidden && entry.file_name().as_bytes().first() == Some(&b'.') {
+                    return false;
+                }
+                let ignored = match matched(&stack, entry.path(), is_dir) {
+                    Match::None => parents.as_ref().is_some_and(|(abs_root, ignores)| {
+                        let rel = entry.path().strip_prefix(&root).unwrap_or(entry.path());
+                        matched(ignores, &abs_root.join(rel), is_dir) == Match::Ignore
+                    }),
+                    m => m == Match::Ignore,
+                };
+                if ignored {
+                    return false;
+                }
+            }
+
+            if is_dir {
+                let ignores = load_ignores(entry.path(), opts);
+                if !ignores.is_empty() {
+                    stack.push((depth, ignores));
+                }
+            }
+            true
+        })
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .map(DirEntry::into_path)
+}
+
+fn matched(stack: &[(usize, Vec<Gitignore>)], path: &Path, is_dir: bool) -> Match {
+    for (_, ignores) in stack.iter().rev() {
+        for ignore in ignores {
+            match ignore.matched(path, is_dir) {
+                Match::None => (),
+                m => return m,
+            }
+        }
+    }
+    Match::None
+}
+
+/// Loads the ignore files of the directories above `root` up to the root of the git repository
+/// it is in, if any, together with the absolute path of `root` their rules are matched under.
+fn parent_ignores(root: &Path, opts: WalkOptions) -> Option<(PathBuf, IgnoreStack)> {
+    let abs_root = root.canonicalize().ok()?;
+    let git_root = abs_root.ancestors().find(|dir| dir.join(".git").exists())?;
+
+    let stack = abs_root
+        .ancestors()
+        .skip(1)
+        .take_while(|dir| dir.starts_with(git_root))
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .map(|dir| (0, load_ignores(dir, opts)))
+        .filter(|(_, ignores)| !ignores.is_empty())
+        .collect::<Vec<_>>();
+    (!stack.is_empty()).then_some((abs_root, stack))
+}
+
+fn load_ignores(dir: &Path, opts: WalkOptions) -> Vec<Gitignore> {
+    let names: &[(&str, bool)] = &[
+        (".ignore", !opts.no_ignore_dot),
+        (".gitignore", !opts.no_ignore_vcs),
+    ];
+
+    names
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .filter_map(|(name, _)| Gitignore::from_file(&dir.join(name)).ok())
+        .filter(|ignore| !ignore.is_empty())
+        .collect()
+}